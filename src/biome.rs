@@ -0,0 +1,374 @@
+// The biomes of the game, used to color grass, leaves and water like minecraft does
+// And to draw biome maps
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+// Biomes whose grass and leaves don't use the colormaps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Normal,
+    Swamp,
+    Badlands,
+    DarkForest,
+    Mangrove,
+}
+
+struct Biome {
+    // The current name first, then the ones used by older versions
+    names: &'static [&'static str],
+    temperature: f32,
+    downfall: f32,
+    kind: Kind,
+    water: [u8; 3],
+    // The color used for the biome in biome maps
+    color: [u8; 3],
+}
+
+const WATER: [u8; 3] = [0x3F, 0x76, 0xE4];
+const SWAMP_WATER: [u8; 3] = [0x61, 0x7B, 0x64];
+const WARM_WATER: [u8; 3] = [0x43, 0xD5, 0xEE];
+const LUKEWARM_WATER: [u8; 3] = [0x45, 0xAD, 0xF2];
+const COLD_WATER: [u8; 3] = [0x3D, 0x57, 0xD6];
+const FROZEN_WATER: [u8; 3] = [0x39, 0x38, 0xC9];
+
+const fn biome(
+    names: &'static [&'static str],
+    temperature: f32,
+    downfall: f32,
+    color: u32,
+) -> Biome {
+    Biome {
+        names,
+        temperature,
+        downfall,
+        kind: Kind::Normal,
+        water: WATER,
+        color: [(color >> 16) as u8, (color >> 8) as u8, color as u8],
+    }
+}
+
+const fn with_kind(mut biome: Biome, kind: Kind) -> Biome {
+    biome.kind = kind;
+    biome
+}
+
+const fn with_water(mut biome: Biome, water: [u8; 3]) -> Biome {
+    biome.water = water;
+    biome
+}
+
+// The index of a biome in `BIOMES`
+pub type BiomeId = u16;
+
+// Used for unknown biomes (ex: from datapacks) and chunks without biomes
+// They are tinted like plains, but have their own color in biome maps, which no other biome uses
+pub const UNKNOWN: BiomeId = 0;
+
+#[rustfmt::skip]
+const BIOMES: &[Biome] = &[
+    biome(&[], 0.8, 0.4, 0x00FF00),
+    biome(&["minecraft:plains"], 0.8, 0.4, 0x8DB360),
+    biome(&["minecraft:sunflower_plains"], 0.8, 0.4, 0xB5DB88),
+    biome(&["minecraft:snowy_plains", "minecraft:snowy_tundra", "minecraft:ice_plains"], 0.0, 0.5, 0xFFFFFF),
+    biome(&["minecraft:ice_spikes"], 0.0, 0.5, 0xB4DCDC),
+    biome(&["minecraft:desert"], 2.0, 0.0, 0xFA9418),
+    biome(&["minecraft:desert_hills"], 2.0, 0.0, 0xD25F12),
+    biome(&["minecraft:desert_lakes"], 2.0, 0.0, 0xFFBC40),
+    with_kind(with_water(biome(&["minecraft:swamp", "minecraft:swampland"], 0.8, 0.9, 0x07F9B2), SWAMP_WATER), Kind::Swamp),
+    with_kind(with_water(biome(&["minecraft:swamp_hills"], 0.8, 0.9, 0x2FFFDA), SWAMP_WATER), Kind::Swamp),
+    with_kind(with_water(biome(&["minecraft:mangrove_swamp"], 0.8, 0.9, 0x2CCC8E), [0x3A, 0x7A, 0x6A]), Kind::Mangrove),
+    biome(&["minecraft:forest"], 0.7, 0.8, 0x056621),
+    biome(&["minecraft:wooded_hills", "minecraft:forest_hills"], 0.7, 0.8, 0x22551C),
+    biome(&["minecraft:flower_forest"], 0.7, 0.8, 0x2D8E49),
+    biome(&["minecraft:birch_forest"], 0.6, 0.6, 0x307444),
+    biome(&["minecraft:birch_forest_hills"], 0.6, 0.6, 0x1F5F32),
+    biome(&["minecraft:old_growth_birch_forest", "minecraft:tall_birch_forest"], 0.6, 0.6, 0x589C6C),
+    biome(&["minecraft:tall_birch_hills"], 0.6, 0.6, 0x47875A),
+    with_kind(biome(&["minecraft:dark_forest", "minecraft:roofed_forest"], 0.7, 0.8, 0x40511A), Kind::DarkForest),
+    with_kind(biome(&["minecraft:dark_forest_hills"], 0.7, 0.8, 0x687942), Kind::DarkForest),
+    biome(&["minecraft:old_growth_pine_taiga", "minecraft:giant_tree_taiga", "minecraft:mega_taiga"], 0.3, 0.8, 0x596651),
+    biome(&["minecraft:giant_tree_taiga_hills"], 0.3, 0.8, 0x454F3E),
+    biome(&["minecraft:old_growth_spruce_taiga", "minecraft:giant_spruce_taiga"], 0.25, 0.8, 0x818E79),
+    biome(&["minecraft:giant_spruce_taiga_hills"], 0.25, 0.8, 0x6D7766),
+    biome(&["minecraft:taiga"], 0.25, 0.8, 0x0B6659),
+    biome(&["minecraft:taiga_hills"], 0.25, 0.8, 0x163933),
+    biome(&["minecraft:taiga_mountains"], 0.25, 0.8, 0x338E81),
+    biome(&["minecraft:snowy_taiga", "minecraft:taiga_cold"], -0.5, 0.4, 0x31554A),
+    biome(&["minecraft:snowy_taiga_hills"], -0.5, 0.4, 0x243F36),
+    biome(&["minecraft:snowy_taiga_mountains"], -0.5, 0.4, 0x597D72),
+    biome(&["minecraft:savanna"], 1.2, 0.0, 0xBDB25F),
+    biome(&["minecraft:savanna_plateau"], 1.0, 0.0, 0xA79D64),
+    biome(&["minecraft:windswept_savanna", "minecraft:shattered_savanna"], 1.1, 0.0, 0xE5DA87),
+    biome(&["minecraft:shattered_savanna_plateau"], 1.0, 0.0, 0xCFC58C),
+    biome(&["minecraft:windswept_hills", "minecraft:mountains", "minecraft:extreme_hills"], 0.2, 0.3, 0x606060),
+    biome(&["minecraft:windswept_gravelly_hills", "minecraft:gravelly_mountains"], 0.2, 0.3, 0x888888),
+    biome(&["minecraft:modified_gravelly_mountains"], 0.2, 0.3, 0x789878),
+    biome(&["minecraft:windswept_forest", "minecraft:wooded_mountains"], 0.2, 0.3, 0x507050),
+    biome(&["minecraft:mountain_edge"], 0.2, 0.3, 0x72789A),
+    biome(&["minecraft:snowy_mountains"], 0.0, 0.5, 0xA0A0A0),
+    biome(&["minecraft:jungle"], 0.95, 0.9, 0x537B09),
+    biome(&["minecraft:jungle_hills"], 0.95, 0.9, 0x2C4205),
+    biome(&["minecraft:modified_jungle"], 0.95, 0.9, 0x7BA331),
+    biome(&["minecraft:sparse_jungle", "minecraft:jungle_edge"], 0.95, 0.8, 0x628B17),
+    biome(&["minecraft:modified_jungle_edge"], 0.95, 0.8, 0x8AB33F),
+    biome(&["minecraft:bamboo_jungle"], 0.95, 0.9, 0x768E14),
+    biome(&["minecraft:bamboo_jungle_hills"], 0.95, 0.9, 0x3B470A),
+    with_kind(biome(&["minecraft:badlands", "minecraft:mesa"], 2.0, 0.0, 0xD94515), Kind::Badlands),
+    with_kind(biome(&["minecraft:eroded_badlands"], 2.0, 0.0, 0xFF6D3D), Kind::Badlands),
+    with_kind(biome(&["minecraft:wooded_badlands", "minecraft:wooded_badlands_plateau"], 2.0, 0.0, 0xB09765), Kind::Badlands),
+    with_kind(biome(&["minecraft:badlands_plateau"], 2.0, 0.0, 0xCA8C65), Kind::Badlands),
+    with_kind(biome(&["minecraft:modified_wooded_badlands_plateau"], 2.0, 0.0, 0xD8BF8D), Kind::Badlands),
+    with_kind(biome(&["minecraft:modified_badlands_plateau"], 2.0, 0.0, 0xF2B48D), Kind::Badlands),
+    biome(&["minecraft:meadow"], 0.5, 0.8, 0x60A445),
+    biome(&["minecraft:cherry_grove"], 0.5, 0.8, 0xFF91C8),
+    biome(&["minecraft:grove"], -0.2, 0.8, 0x47726C),
+    biome(&["minecraft:snowy_slopes"], -0.3, 0.9, 0xC4C4C4),
+    biome(&["minecraft:frozen_peaks"], -0.7, 0.9, 0xB0B3CE),
+    biome(&["minecraft:jagged_peaks"], -0.7, 0.9, 0xDCDCC8),
+    biome(&["minecraft:stony_peaks"], 1.0, 0.3, 0x7B8F74),
+    biome(&["minecraft:river"], 0.5, 0.5, 0x0000FF),
+    with_water(biome(&["minecraft:frozen_river"], 0.0, 0.5, 0xA0A0FF), FROZEN_WATER),
+    biome(&["minecraft:beach"], 0.8, 0.4, 0xFADE55),
+    biome(&["minecraft:snowy_beach", "minecraft:cold_beach"], 0.05, 0.3, 0xFAF0C0),
+    biome(&["minecraft:stony_shore", "minecraft:stone_shore", "minecraft:stone_beach"], 0.2, 0.3, 0xA2A284),
+    biome(&["minecraft:ocean"], 0.5, 0.5, 0x000070),
+    biome(&["minecraft:deep_ocean"], 0.5, 0.5, 0x000030),
+    with_water(biome(&["minecraft:warm_ocean"], 0.5, 0.5, 0x0000AC), WARM_WATER),
+    with_water(biome(&["minecraft:deep_warm_ocean"], 0.5, 0.5, 0x000050), WARM_WATER),
+    with_water(biome(&["minecraft:lukewarm_ocean"], 0.5, 0.5, 0x000090), LUKEWARM_WATER),
+    with_water(biome(&["minecraft:deep_lukewarm_ocean"], 0.5, 0.5, 0x000040), LUKEWARM_WATER),
+    with_water(biome(&["minecraft:cold_ocean"], 0.5, 0.5, 0x202070), COLD_WATER),
+    with_water(biome(&["minecraft:deep_cold_ocean"], 0.5, 0.5, 0x202038), COLD_WATER),
+    with_water(biome(&["minecraft:frozen_ocean"], 0.0, 0.5, 0x7070D6), FROZEN_WATER),
+    with_water(biome(&["minecraft:deep_frozen_ocean"], 0.5, 0.5, 0x404090), FROZEN_WATER),
+    biome(&["minecraft:mushroom_fields", "minecraft:mushroom_island"], 0.9, 1.0, 0xFF00FF),
+    biome(&["minecraft:mushroom_field_shore"], 0.9, 1.0, 0xA000FF),
+    biome(&["minecraft:dripstone_caves"], 0.8, 0.4, 0x4E3012),
+    biome(&["minecraft:lush_caves"], 0.5, 0.5, 0x283C00),
+    biome(&["minecraft:deep_dark"], 0.8, 0.4, 0x031F29),
+    biome(&["minecraft:nether_wastes", "minecraft:nether", "minecraft:hell"], 2.0, 0.0, 0xBF3B3B),
+    biome(&["minecraft:soul_sand_valley"], 2.0, 0.0, 0x5E3830),
+    biome(&["minecraft:crimson_forest"], 2.0, 0.0, 0xDD0808),
+    biome(&["minecraft:warped_forest"], 2.0, 0.0, 0x49907B),
+    biome(&["minecraft:basalt_deltas"], 2.0, 0.0, 0x403636),
+    biome(&["minecraft:the_end", "minecraft:sky"], 0.5, 0.5, 0x8080FF),
+    biome(&["minecraft:small_end_islands"], 0.5, 0.5, 0x4B4BAB),
+    biome(&["minecraft:end_midlands"], 0.5, 0.5, 0xC9C959),
+    biome(&["minecraft:end_highlands"], 0.5, 0.5, 0xB5B536),
+    biome(&["minecraft:end_barrens"], 0.5, 0.5, 0x7070CC),
+    biome(&["minecraft:the_void"], 0.5, 0.5, 0x000000),
+];
+
+// The names of the numeric ids used before 1.18, by id
+#[rustfmt::skip]
+const NUMERIC_IDS: &[(i32, &str)] = &[
+    (0, "ocean"), (1, "plains"), (2, "desert"), (3, "mountains"), (4, "forest"),
+    (5, "taiga"), (6, "swamp"), (7, "river"), (8, "nether_wastes"), (9, "the_end"),
+    (10, "frozen_ocean"), (11, "frozen_river"), (12, "snowy_tundra"), (13, "snowy_mountains"),
+    (14, "mushroom_fields"), (15, "mushroom_field_shore"), (16, "beach"), (17, "desert_hills"),
+    (18, "wooded_hills"), (19, "taiga_hills"), (20, "mountain_edge"), (21, "jungle"),
+    (22, "jungle_hills"), (23, "jungle_edge"), (24, "deep_ocean"), (25, "stone_shore"),
+    (26, "snowy_beach"), (27, "birch_forest"), (28, "birch_forest_hills"), (29, "dark_forest"),
+    (30, "snowy_taiga"), (31, "snowy_taiga_hills"), (32, "giant_tree_taiga"),
+    (33, "giant_tree_taiga_hills"), (34, "wooded_mountains"), (35, "savanna"),
+    (36, "savanna_plateau"), (37, "badlands"), (38, "wooded_badlands_plateau"),
+    (39, "badlands_plateau"), (40, "small_end_islands"), (41, "end_midlands"),
+    (42, "end_highlands"), (43, "end_barrens"), (44, "warm_ocean"), (45, "lukewarm_ocean"),
+    (46, "cold_ocean"), (47, "deep_warm_ocean"), (48, "deep_lukewarm_ocean"),
+    (49, "deep_cold_ocean"), (50, "deep_frozen_ocean"), (127, "the_void"),
+    (129, "sunflower_plains"), (130, "desert_lakes"), (131, "gravelly_mountains"),
+    (132, "flower_forest"), (133, "taiga_mountains"), (134, "swamp_hills"), (140, "ice_spikes"),
+    (149, "modified_jungle"), (151, "modified_jungle_edge"), (155, "tall_birch_forest"),
+    (156, "tall_birch_hills"), (157, "dark_forest_hills"), (158, "snowy_taiga_mountains"),
+    (160, "giant_spruce_taiga"), (161, "giant_spruce_taiga_hills"),
+    (162, "modified_gravelly_mountains"), (163, "shattered_savanna"),
+    (164, "shattered_savanna_plateau"), (165, "eroded_badlands"),
+    (166, "modified_wooded_badlands_plateau"), (167, "modified_badlands_plateau"),
+    (168, "bamboo_jungle"), (169, "bamboo_jungle_hills"), (170, "soul_sand_valley"),
+    (171, "crimson_forest"), (172, "warped_forest"), (173, "basalt_deltas"),
+    (174, "dripstone_caves"), (175, "lush_caves"),
+];
+
+// Ex: "minecraft:plains"
+pub fn from_name(name: &str) -> BiomeId {
+    find(name).unwrap_or(UNKNOWN)
+}
+
+pub fn find(name: &str) -> Option<BiomeId> {
+    BIOMES
+        .iter()
+        .position(|biome| biome.names.contains(&name))
+        .map(|index| index as BiomeId)
+}
+
+// The numeric ids used before 1.18
+// Every biome of old chunks goes through here, so the ids are looked up in a table
+pub fn from_id(id: i32) -> BiomeId {
+    static IDS: OnceLock<[BiomeId; 256]> = OnceLock::new();
+    let ids = IDS.get_or_init(|| {
+        let mut ids = [UNKNOWN; 256];
+        for (id, name) in NUMERIC_IDS {
+            ids[*id as usize] = from_name(&format!("minecraft:{}", name));
+        }
+        ids
+    });
+    match id {
+        0..=255 => ids[id as usize],
+        _ => UNKNOWN,
+    }
+}
+
+// The color of each biome in biome maps, by id
+// The colors in `custom` (ex: "minecraft:plains": [141, 179, 96]) replace the default ones
+pub fn map_colors(custom: &HashMap<String, [u8; 3]>) -> Vec<[u8; 3]> {
+    let mut colors: Vec<[u8; 3]> = BIOMES.iter().map(|biome| biome.color).collect();
+    for (name, color) in custom {
+        match find(name) {
+            Some(biome) => colors[usize::from(biome)] = *color,
+            None => println!("Unknown biome {} in the biome colors file", name),
+        }
+    }
+    colors
+}
+
+// How a block is colored by the biome it is in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tint {
+    Grass,
+    Foliage,
+    Water,
+}
+
+pub fn tint_of(name: &str) -> Option<Tint> {
+    match name {
+        "minecraft:grass_block"
+        | "minecraft:grass"
+        | "minecraft:short_grass"
+        | "minecraft:tall_grass"
+        | "minecraft:fern"
+        | "minecraft:large_fern"
+        | "minecraft:sugar_cane" => Some(Tint::Grass),
+        "minecraft:oak_leaves"
+        | "minecraft:jungle_leaves"
+        | "minecraft:acacia_leaves"
+        | "minecraft:dark_oak_leaves"
+        | "minecraft:mangrove_leaves"
+        | "minecraft:vine" => Some(Tint::Foliage),
+        "minecraft:water" | "minecraft:bubble_column" => Some(Tint::Water),
+        _ => None,
+    }
+}
+
+// The colors of grass, foliage and water in each biome
+pub struct BiomeColors {
+    grass: Vec<[u8; 3]>,
+    foliage: Vec<[u8; 3]>,
+    water: Vec<[u8; 3]>,
+}
+
+impl BiomeColors {
+    // grass and foliage are the colormaps from the textures of the game
+    pub fn new(grass: &image::RgbaImage, foliage: &image::RgbaImage) -> BiomeColors {
+        let grass = BIOMES
+            .iter()
+            .map(|biome| match biome.kind {
+                Kind::Swamp | Kind::Mangrove => [0x6A, 0x70, 0x39],
+                Kind::Badlands => [0x90, 0x81, 0x4D],
+                // Dark forests mix the color with a darker green
+                Kind::DarkForest => {
+                    let [r, g, b] = colormap_color(grass, biome);
+                    [
+                        ((u16::from(r & 0xFE) + 0x28) >> 1) as u8,
+                        ((u16::from(g & 0xFE) + 0x34) >> 1) as u8,
+                        ((u16::from(b & 0xFE) + 0x0A) >> 1) as u8,
+                    ]
+                }
+                Kind::Normal => colormap_color(grass, biome),
+            })
+            .collect();
+        let foliage = BIOMES
+            .iter()
+            .map(|biome| match biome.kind {
+                Kind::Swamp => [0x6A, 0x70, 0x39],
+                Kind::Badlands => [0x9E, 0x81, 0x4D],
+                Kind::Mangrove => [0x8D, 0xB1, 0x27],
+                Kind::Normal | Kind::DarkForest => colormap_color(foliage, biome),
+            })
+            .collect();
+        let water = BIOMES.iter().map(|biome| biome.water).collect();
+
+        BiomeColors {
+            grass,
+            foliage,
+            water,
+        }
+    }
+
+    pub fn get(&self, tint: Tint, biome: BiomeId) -> [u8; 3] {
+        let colors = match tint {
+            Tint::Grass => &self.grass,
+            Tint::Foliage => &self.foliage,
+            Tint::Water => &self.water,
+        };
+        colors[usize::from(biome)]
+    }
+}
+
+// Hotter biomes are on the left of the colormap, and wetter ones on the bottom
+fn colormap_color(colormap: &image::RgbaImage, biome: &Biome) -> [u8; 3] {
+    let temperature = biome.temperature.clamp(0.0, 1.0);
+    let downfall = biome.downfall.clamp(0.0, 1.0) * temperature;
+    let x = ((1.0 - temperature) * (colormap.width() - 1) as f32) as u32;
+    let y = ((1.0 - downfall) * (colormap.height() - 1) as f32) as u32;
+    let pixel = colormap.get_pixel(x, y);
+    [pixel[0], pixel[1], pixel[2]]
+}
+
+// Multiplies a gray color by the color of the biome
+pub fn tint_color(color: [u8; 3], tint: [u8; 3]) -> [u8; 3] {
+    let mut tinted = [0; 3];
+    for i in 0..3 {
+        tinted[i] = (u16::from(color[i]) * u16::from(tint[i]) / 255) as u8;
+    }
+    tinted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_biomes() {
+        assert_eq!(from_name("datapack:volcano"), UNKNOWN);
+        assert_eq!(from_id(-5), UNKNOWN);
+        assert_eq!(from_id(256), UNKNOWN);
+        assert_eq!(from_id(100), UNKNOWN);
+        assert_eq!(from_id(1), from_name("minecraft:plains"));
+        assert_eq!(from_id(12), from_name("minecraft:snowy_plains"));
+        assert_eq!(from_id(175), from_name("minecraft:lush_caves"));
+        assert_ne!(from_name("minecraft:plains"), UNKNOWN);
+        assert_eq!(find("datapack:volcano"), None);
+
+        let colors = map_colors(&HashMap::new());
+        assert_eq!(colors[usize::from(UNKNOWN)], [0x00, 0xFF, 0x00]);
+        for (biome, color) in colors.iter().enumerate().skip(1) {
+            let name = BIOMES[biome].names[0];
+            assert_ne!(*color, colors[usize::from(UNKNOWN)], "{}", name);
+        }
+
+        // Grass and leaves are tinted like in plains
+        let mut colormap = image::RgbaImage::new(256, 256);
+        for (x, y, pixel) in colormap.enumerate_pixels_mut() {
+            *pixel = image::Rgba([x as u8, y as u8, 0, 255]);
+        }
+        let biome_colors = BiomeColors::new(&colormap, &colormap);
+        let plains = from_name("minecraft:plains");
+        for tint in [Tint::Grass, Tint::Foliage, Tint::Water] {
+            assert_eq!(
+                biome_colors.get(tint, UNKNOWN),
+                biome_colors.get(tint, plains)
+            );
+        }
+    }
+}
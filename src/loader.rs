@@ -1,8 +1,11 @@
 use serde_json::Value;
 use std::fs;
+use std::path::Path;
 
 use std::collections::{HashMap, HashSet};
 
+use super::biome::{self, BiomeColors, BiomeId};
+
 fn image_avg(img: &image::RgbaImage) -> [u8; 3] {
     let mut r = 0;
     let mut g = 0;
@@ -50,10 +53,10 @@ fn get_texture(model: &str) -> Option<String> {
             Some(top.as_str().unwrap().to_owned())
         } else if let Some(all) = textures.get("all") {
             Some(all.as_str().unwrap().to_owned())
-        } else if let Some(particle) = textures.get("particle") {
-            Some(particle.as_str().unwrap().to_owned())
         } else {
-            None
+            textures
+                .get("particle")
+                .map(|particle| particle.as_str().unwrap().to_owned())
         }
     } else {
         None
@@ -102,17 +105,29 @@ pub struct TextureLoader {
     textures_map: HashMap<(String, String), Option<usize>>,
     // Block which have a white and gray texture that needs to be painted
     biome_blocks: HashMap<String, [i16; 3]>,
+    // The colors of grass, leaves and water, which are painted depending on the biome
+    biome_colors: BiomeColors,
 }
 
 impl TextureLoader {
-    pub fn new(biome_blocks: HashMap<String, [i16; 3]>) -> TextureLoader {
+    pub fn new(
+        biome_blocks: HashMap<String, [i16; 3]>,
+        biome_colors: BiomeColors,
+    ) -> TextureLoader {
         TextureLoader {
             textures: Vec::new(),
             textures_map: HashMap::new(),
             biome_blocks,
+            biome_colors,
         }
     }
 
+    // The color by which the texture of the block is multiplied in the given biome
+    // None for the blocks which don't change color between biomes
+    pub fn biome_color(&self, name: &str, biome: BiomeId) -> Option<[u8; 3]> {
+        biome::tint_of(name).map(|tint| self.biome_colors.get(tint, biome))
+    }
+
     pub fn get_texture(&self, index: usize) -> &(image::RgbaImage, bool, [u8; 3]) {
         &self.textures[index]
     }
@@ -135,8 +150,11 @@ impl TextureLoader {
                 // The color must be tainted for blocks like leaves, grass and water
                 // Sometimes the taint is hardcoded in minecraft
                 // so the only way to reproduce is to define it ourselves
+                // Blocks colored by the biome are painted while rendering instead
                 if let Some(taint) = self.biome_blocks.get(name) {
-                    taint_image(&mut texture, *taint);
+                    if biome::tint_of(name).is_none() {
+                        taint_image(&mut texture, *taint);
+                    }
                 }
                 let avg = image_avg(&texture);
 
@@ -161,8 +179,10 @@ impl TextureLoader {
 const SETTINGS_FOLDER: &str = "settings/";
 const BIOME_BLOCKS_FILE: &str = "biome_blocks.json";
 const IGNORE_BLOCKS_FILE: &str = "ignore_blocks.json";
+const BIOME_COLORS_FILE: &str = "biome_colors.json";
 
 const BLOCKSTATES_FOLDER: &str = "resources/blockstates";
+const COLORMAP_FOLDER: &str = "resources/textures/colormap/";
 
 use std::io;
 
@@ -176,6 +196,41 @@ pub fn load_biome_blocks() -> io::Result<HashMap<String, [i16; 3]>> {
     Ok(serde_json::from_str(&biome_blocks)?)
 }
 
+// The colors of the biomes in biome maps
+// The file is optional, and only needs the colors which are different from the default ones
+pub fn load_biome_map_colors() -> io::Result<Vec<[u8; 3]>> {
+    let custom = match fs::read_to_string(SETTINGS_FOLDER.to_owned() + BIOME_COLORS_FILE) {
+        Ok(biome_colors) => serde_json::from_str(&biome_colors)?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => HashMap::new(),
+        Err(err) => return Err(err),
+    };
+    Ok(biome::map_colors(&custom))
+}
+
+// The colors of grass and leaves in plains, used when the colormaps are missing
+const DEFAULT_GRASS: [u8; 3] = [0x91, 0xBD, 0x59];
+const DEFAULT_FOLIAGE: [u8; 3] = [0x77, 0xAB, 0x2F];
+
+// The colormaps of grass and leaves, which give their color from the biome's temperature and downfall
+// Without a colormap all the biomes get the color of plains
+pub fn load_biome_colors() -> io::Result<BiomeColors> {
+    let open = |file: &str, default: [u8; 3]| {
+        let path = COLORMAP_FOLDER.to_owned() + file;
+        if !Path::new(&path).exists() {
+            println!("Missing {}, the default color is used for every biome", path);
+            let [r, g, b] = default;
+            return Ok(image::RgbaImage::from_pixel(1, 1, image::Rgba([r, g, b, 255])));
+        }
+        image::open(path)
+            .map(|colormap| colormap.to_rgba())
+            .map_err(|err| io::Error::other(err.to_string()))
+    };
+    Ok(BiomeColors::new(
+        &open("grass.png", DEFAULT_GRASS)?,
+        &open("foliage.png", DEFAULT_FOLIAGE)?,
+    ))
+}
+
 // Associate to every block in game
 // Their possible different graphic options
 // Ex: snowy, waterlogged
@@ -212,8 +267,8 @@ pub fn load_graphic_props() -> io::Result<HashMap<String, HashMap<String, usize>
                 let propvalues = variant.split(',');
                 for (i, prop_value) in propvalues.enumerate() {
                     // Get the property
-                    if let Some(prop) = prop_value.split('=').nth(0) {
-                        if prop != "" {
+                    if let Some(prop) = prop_value.split('=').next() {
+                        if !prop.is_empty() {
                             used_variants.insert(prop.to_owned(), i);
                         }
                     }
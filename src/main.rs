@@ -1,26 +1,94 @@
+mod biome;
 mod loader;
 mod map;
 mod nbt;
 mod renderer;
 
 use rayon::prelude::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::RwLock;
 
-fn folder_trim(path: &PathBuf) -> String {
+fn folder_trim(path: &Path) -> String {
     path.strip_prefix("/").unwrap_or(path).to_str().unwrap().replace('/', ":")
 }
 
-fn parse_name(name: &PathBuf) -> (i32, i32) {
-    let sections: Vec<&str> = name.to_str().unwrap().split('.').collect();
-    (sections[1].parse().unwrap(), sections[2].parse().unwrap())
+// The height of the bedrock roof of the nether
+const NETHER_ROOF: i32 = 127;
+
+// The folder of a dimension, which contains its region folder
+fn dimension_folder(world: &Path, dimension: &str) -> PathBuf {
+    match dimension {
+        "overworld" => world.to_owned(),
+        "nether" => world.join("DIM-1"),
+        "end" => world.join("DIM1"),
+        // Datapack dimensions, ex: namespace:name
+        _ => match dimension.split_once(':') {
+            Some((namespace, name)) => world.join("dimensions").join(namespace).join(name),
+            None => world.join(dimension),
+        },
+    }
+}
+
+// What the images show
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    // The top block of each column, as one pixel
+    Blocks,
+    // The texture of the top block of each column
+    Textures,
+    // The biome of the top block of each column
+    Biome,
+}
+
+// Loads the region next to the one at `position` in the given direction (ex: (0, -1) for north)
+// Only the chunks on the side touching the region at `position` are loaded
+fn load_neighbor(
+    regions: &HashMap<(i32, i32), PathBuf>,
+    position: (i32, i32),
+    direction: (i32, i32),
+    mmap: bool,
+    heightmap: map::Heightmap,
+    graphic_set: &HashMap<String, HashMap<String, usize>>,
+) -> Option<map::Region> {
+    let path = regions.get(&(position.0 + direction.0, position.1 + direction.1))?;
+    let last = map::REGION_SIZE - 1;
+    let side = |chunk: usize, step: i32| match step {
+        -1 => chunk == last,
+        1 => chunk == 0,
+        _ => true,
+    };
+    map::Region::from_file_filtered(path, mmap, heightmap, graphic_set, |x, z| {
+        side(x, direction.0) && side(z, direction.1)
+    })
+    .ok()
+}
+
+// The textures of the blocks, tinted by the colormaps of the biomes
+fn load_textures() -> RwLock<loader::TextureLoader> {
+    let biome_blocks = loader::load_biome_blocks().unwrap_or_else(|err| {
+        println!("Error loading biome blocks file: {}", err);
+        std::process::exit(0)
+    });
+    let biome_colors = loader::load_biome_colors().unwrap_or_else(|err| {
+        println!("Error loading colormaps from resources folder: {}", err);
+        std::process::exit(0)
+    });
+    RwLock::new(loader::TextureLoader::new(biome_blocks, biome_colors))
 }
 
 // This should return the files list
-fn save_images(files: &[(PathBuf, PathBuf)], generate_textures: bool) {
+fn save_images(
+    files: &[(PathBuf, PathBuf)],
+    regions: &HashMap<(i32, i32), PathBuf>,
+    mode: Mode,
+    roof: Option<i32>,
+    mmap: bool,
+    heightmap: map::Heightmap,
+    shade: bool,
+) {
     // Load all the settings
     let ignore = loader::load_ignore_blocks().unwrap_or_else(|err| {
         println!("Error loading ignore blocks file: {}", err);
@@ -30,11 +98,14 @@ fn save_images(files: &[(PathBuf, PathBuf)], generate_textures: bool) {
         println!("Error loading blockstates from resources folder: {}", err);
         std::process::exit(0)
     });
-    let biome_blocks = loader::load_biome_blocks().unwrap_or_else(|err| {
-        println!("Error loading biome blocks file: {}", err);
-        std::process::exit(0)
+    // Biome maps don't need the textures, block images don't need the colors of the biomes
+    let textures = (mode != Mode::Biome).then(load_textures);
+    let biome_map_colors = (mode == Mode::Biome).then(|| {
+        loader::load_biome_map_colors().unwrap_or_else(|err| {
+            println!("Error loading biome colors file: {}", err);
+            std::process::exit(0)
+        })
     });
-    let textures = RwLock::new(loader::TextureLoader::new(biome_blocks));
 
     let progress = AtomicU32::new(0);
     // Generate all the images
@@ -51,20 +122,76 @@ fn save_images(files: &[(PathBuf, PathBuf)], generate_textures: bool) {
         );
 
         // If there was an error reading this region, generate an empty one
-        let region = map::Region::from_file(&region_path, &graphic_set)
+        let region = map::Region::from_file(region_path, mmap, heightmap, &graphic_set)
             .unwrap_or_else(|_| map::Region::new_empty());
 
-        if generate_textures {
-            renderer::image_chunk_textures(&region, &ignore, &textures).save(&image_path)
-        } else {
-            renderer::image_chunk(&region, &ignore, &textures).save(&image_path)
+        match mode {
+            Mode::Blocks => {
+                let textures = textures.as_ref().unwrap();
+                let heights = if shade {
+                    let position = map::parse_region_name(region_path).unwrap();
+                    let load = |direction| {
+                        load_neighbor(regions, position, direction, mmap, heightmap, &graphic_set)
+                    };
+                    let (north, west) = (load((0, -1)), load((-1, 0)));
+                    let (north, west) = (north.as_ref(), west.as_ref());
+                    Some(renderer::Heights::new(&region, north, west, &ignore, roof))
+                } else {
+                    None
+                };
+                renderer::image_chunk(&region, &ignore, textures, roof, heights.as_ref())
+                    .save(image_path)
+            }
+            Mode::Textures => {
+                let textures = textures.as_ref().unwrap();
+                renderer::image_chunk_textures(&region, &ignore, textures, roof).save(image_path)
+            }
+            Mode::Biome => {
+                let colors = biome_map_colors.as_ref().unwrap();
+                renderer::image_biomes(&region, &ignore, colors, roof).save(image_path)
+            }
         }
         .unwrap();
     });
 }
 
+// Prints the problems of each region, and removes the broken chunks if repair is set
+fn check_regions(files: &[(PathBuf, PathBuf)], repair: bool) {
+    let mut broken_regions = 0;
+    for (region_path, _) in files {
+        let report = match map::check::check_region(region_path) {
+            Ok(report) => report,
+            Err(err) => {
+                println!("{}: can't read the region: {}", region_path.display(), err);
+                broken_regions += 1;
+                continue;
+            }
+        };
+        if report.problems.is_empty() {
+            continue;
+        }
+
+        report.print(region_path);
+        broken_regions += 1;
+        if repair {
+            match map::check::repair_region(region_path, &report) {
+                Ok(backup) => println!(
+                    "{}: repaired, the old region was saved in {}",
+                    region_path.display(),
+                    backup.display()
+                ),
+                Err(err) => {
+                    println!("{}: can't repair the region: {}", region_path.display(), err)
+                }
+            }
+        }
+    }
+
+    println!("{} of {} regions have problems", broken_regions, files.len());
+}
+
 fn save_collage(
-    images_folder: &PathBuf,
+    images_folder: &Path,
     files: &HashMap<(i32, i32), PathBuf>,
     resolution: (u32, u32),
 ) {
@@ -86,7 +213,7 @@ fn save_collage(
 
         let img = image::open(file).unwrap();
         let img = img.as_rgb8().unwrap();
-        image::imageops::replace(&mut collage, &img, pixel.0, pixel.1);
+        image::imageops::replace(&mut collage, img, pixel.0, pixel.1);
     }
 
     collage
@@ -99,24 +226,77 @@ fn main() {
     let matches = clap::App::from_yaml(yaml).get_matches();
 
     // Get the command line arguments
-    let generate_textures = matches.is_present("textures");
+    let mode = match matches.value_of("mode") {
+        Some("biome") => Mode::Biome,
+        _ if matches.is_present("textures") => Mode::Textures,
+        _ => Mode::Blocks,
+    };
+    let shade = matches.is_present("shade");
     let update = matches.is_present("update");
-    let region_folder = Path::new(matches.value_of("world").unwrap()).join("region");
+    let check = matches.is_present("check");
+    let repair = matches.is_present("repair");
+    let mmap = matches.is_present("mmap");
+    let world_folder = Path::new(matches.value_of("world").unwrap());
+    let dimension = matches.value_of("dimension").unwrap_or("overworld");
+    let region_folder = dimension_folder(world_folder, dimension).join("region");
+    // The nether has a bedrock roof, which would hide everything else
+    let roof = match matches.value_of("roof") {
+        Some(roof) => Some(roof.parse().unwrap_or_else(|_| {
+            println!("Invalid roof height: {}", roof);
+            std::process::exit(0)
+        })),
+        None if dimension == "nether" => Some(NETHER_ROOF),
+        None => None,
+    };
+    let heightmap = match matches.value_of("heightmap") {
+        Some("motion_blocking") => map::Heightmap::MotionBlocking,
+        Some("ocean_floor") => map::Heightmap::OceanFloor,
+        _ => map::Heightmap::WorldSurface,
+    };
 
     println!("{}", folder_trim(&region_folder));
     // We move in the images_folder
-    let images_folder = Path::new("images")
-        .join(&folder_trim(&region_folder));
-    
+    let mut images_folder = Path::new("images")
+        .join(folder_trim(&region_folder));
+    // Biome maps are kept apart, so they don't replace the images of the blocks
+    if mode == Mode::Biome {
+        images_folder.push("biome");
+    }
+    // And so are the ones which start from another heightmap
+    if let Some(heightmap) = matches.value_of("heightmap") {
+        if heightmap != "world_surface" {
+            images_folder.push(heightmap);
+        }
+    }
+    // And the shaded ones, which --update wouldn't replace when the regions didn't change
+    if shade {
+        images_folder.push("shade");
+    }
+
     println!("{}", images_folder.display());
 
     // Start the rendering
     std::fs::create_dir_all(&images_folder).unwrap_or_default();
 
+    // Skip files which aren't regions (ex: temporary files, external chunks)
+    let mut regions: HashMap<(i32, i32), PathBuf> = HashMap::new();
+    let entries = fs::read_dir(&region_folder).unwrap_or_else(|err| {
+        println!("Error reading the region folder {}: {}", region_folder.display(), err);
+        std::process::exit(0)
+    });
+    for region_path in entries.map(|entry| entry.unwrap().path()) {
+        let format = map::RegionFormat::from_path(&region_path);
+        if let (Some(format), Some(position)) = (format, map::parse_region_name(&region_path)) {
+            // Worlds converted to Anvil keep the old McRegion files, which are outdated
+            if format == map::RegionFormat::Anvil || !regions.contains_key(&position) {
+                regions.insert(position, region_path);
+            }
+        }
+    }
+
     // Map files to their image_path
-    let files: Vec<_> = fs::read_dir(region_folder)
-        .unwrap()
-        .map(|entry| entry.unwrap().path())
+    let files: Vec<_> = regions
+        .values()
         .map(|region_path| {
             (
                 region_path.clone(),
@@ -128,24 +308,30 @@ fn main() {
         })
         .collect();
 
+    if check || repair {
+        check_regions(&files, repair);
+        return;
+    }
+
     // Get a list of all files that need updating
-    let to_update = if update {
+    let mut to_update: Vec<_> = if update {
         files
             .iter()
             .filter(|(region_path, image_path)| {
-                if let Ok(image_meta) = fs::metadata(&image_path) {
-                    let region_meta = region_path.metadata().unwrap().modified().unwrap();
+                if let Ok(image_meta) = fs::metadata(image_path) {
                     let image_meta = image_meta.modified().unwrap();
-
-                    let region_time = region_meta
-                        .duration_since(std::time::SystemTime::UNIX_EPOCH)
-                        .unwrap();
                     let image_time = image_meta
                         .duration_since(std::time::SystemTime::UNIX_EPOCH)
                         .unwrap();
 
+                    // Use the time when the last chunk was saved, written in the region
+                    // Regions which can't be read are rendered again
+                    let region_time = map::RegionFile::new(region_path)
+                        .and_then(|region| region.last_modified())
+                        .unwrap_or(u32::MAX);
+
                     // Only keep this if the region was updated after the last rendering
-                    region_time > image_time
+                    u64::from(region_time) > image_time.as_secs()
                 } else {
                     // The image wasn't even generated last rendering
                     true
@@ -158,6 +344,24 @@ fn main() {
         files.clone()
     };
 
+    // The regions to the south and east are shaded from the ones next to them, which changed
+    if update && shade {
+        let changed: HashSet<(i32, i32)> = to_update
+            .iter()
+            .filter_map(|(region_path, _)| map::parse_region_name(region_path))
+            .collect();
+        to_update = files
+            .iter()
+            .filter(|(region_path, _)| match map::parse_region_name(region_path) {
+                Some((x, z)) => [(x, z), (x, z - 1), (x - 1, z)]
+                    .iter()
+                    .any(|position| changed.contains(position)),
+                None => false,
+            })
+            .cloned()
+            .collect();
+    }
+
     // The files which need actual updating
     if !to_update.is_empty() {
         println!(
@@ -170,17 +374,19 @@ fn main() {
     }
 
     // Generate the images which need to be updated
-    save_images(&to_update, generate_textures);
+    save_images(&to_update, &regions, mode, roof, mmap, heightmap, shade);
 
     // The list of all generated regions with their coordinates attached
     let images: HashMap<(i32, i32), PathBuf> = files
         .iter()
-        .map(|(_, image_path)| (parse_name(image_path), image_path.clone()))
+        .filter_map(|(_, image_path)| {
+            Some((map::parse_region_name(image_path)?, image_path.clone()))
+        })
         .collect();
 
     // Make a collage of images in which blocks are 16x16 pixels or 1x1 pixels
     println!("Generating collage image");
-    if generate_textures {
+    if mode == Mode::Textures {
         save_collage(&images_folder, &images, (16, 16));
     } else {
         save_collage(&images_folder, &images, (1, 1));
@@ -1,14 +1,27 @@
+use std::borrow::Cow;
 use std::fs::File;
 use std::io::prelude::*;
 
 use std::collections::HashMap;
-use std::io::{BufReader, SeekFrom};
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
-use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt};
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
 use flate2::read::{GzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use indexmap::IndexMap;
+use memmap2::Mmap;
+use rayon::prelude::*;
 
+use serde::Deserialize;
+
+use super::biome::{self, BiomeId};
 use super::nbt;
 
+pub mod check;
+mod legacy;
+
 const SECTOR_SIZE: u64 = 4096;
 
 const EMPTY_BLOCK: &str = "minecraft:air";
@@ -18,55 +31,179 @@ const SECTION_SIZE: usize = 16;
 // How many blocks is a chunk long/deep
 const CHUNK_SIZE: usize = 16;
 // How many chunk is a region long/deep
-const REGION_SIZE: usize = 32;
+pub const REGION_SIZE: usize = 32;
+
+// Both formats store chunks the same way, but the chunks themselves are different
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionFormat {
+    // .mcr files, from Beta 1.3 to 1.1, with chunks 128 blocks high
+    McRegion,
+    // .mca files, from 1.2, with chunks divided in sections
+    Anvil,
+}
+
+impl RegionFormat {
+    // The format of a region file from its extension, None if it isn't a region file
+    pub fn from_path(file_name: &Path) -> Option<RegionFormat> {
+        match file_name.extension()?.to_str()? {
+            "mcr" => Some(RegionFormat::McRegion),
+            "mca" => Some(RegionFormat::Anvil),
+            _ => None,
+        }
+    }
+}
 
 pub struct RegionFile {
-    reader: BufReader<File>,
+    // Locked while reading, as the chunks of a region are read by many threads
+    file: Mutex<File>,
+    // With mmap the whole file is mapped in memory, chunks are decompressed directly from it
+    // Otherwise each part of the file is read when needed
+    mapped: bool,
+    // None without mmap, or while the file is resized
+    map: Option<Mmap>,
+    // The length of the file in bytes
+    len: u64,
+    format: RegionFormat,
+    path: PathBuf,
 }
 
 impl RegionFile {
-    pub fn new(file_name: &std::path::Path) -> std::io::Result<RegionFile> {
-        let file = File::open(file_name)?;
-        let reader = BufReader::new(file);
-        Ok(RegionFile { reader })
+    pub fn new(file_name: &Path) -> std::io::Result<RegionFile> {
+        RegionFile::open(file_name, File::open(file_name)?, false)
+    }
+
+    // Faster with many chunks, but the file must not be changed by other programs
+    // (ex: minecraft) while it is open, or reading it can crash the viewer
+    pub fn new_mapped(file_name: &Path) -> std::io::Result<RegionFile> {
+        RegionFile::open(file_name, File::open(file_name)?, true)
+    }
+
+    fn open(file_name: &Path, file: File, mapped: bool) -> std::io::Result<RegionFile> {
+        let format = RegionFormat::from_path(file_name).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "Not a region file")
+        })?;
+        let mut region = RegionFile {
+            file: Mutex::new(file),
+            mapped,
+            map: None,
+            len: 0,
+            format,
+            path: file_name.to_owned(),
+        };
+        region.reload()?;
+        Ok(region)
+    }
+
+    // Reads the length again, and maps the file again, after its length changed
+    fn reload(&mut self) -> std::io::Result<()> {
+        let file = self.file.get_mut().unwrap();
+        self.len = file.metadata()?.len();
+        if self.mapped {
+            // Safe only as long as the file isn't changed by other programs
+            let map = unsafe { Mmap::map(&*file)? };
+            self.len = map.len() as u64;
+            self.map = Some(map);
+        }
+        Ok(())
+    }
+
+    pub fn format(&self) -> RegionFormat {
+        self.format
+    }
+
+    // The length of the file in bytes
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    // Returns the bytes of the file from start, or an error if the file is too short
+    fn bytes(&self, start: u64, length: u64) -> std::io::Result<Cow<'_, [u8]>> {
+        if start + length > self.len() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "Region file is truncated",
+            ));
+        }
+        if let Some(map) = &self.map {
+            return Ok(Cow::Borrowed(
+                &map[start as usize..(start + length) as usize],
+            ));
+        }
+
+        // If the file was shortened since it was opened this is an error too
+        let mut bytes = vec![0; length as usize];
+        let mut file = self.file.lock().unwrap();
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut bytes)?;
+        Ok(Cow::Owned(bytes))
     }
 
     // Returns (sector, size)
-    pub fn read_header(&mut self) -> std::io::Result<Vec<(u32, u8)>> {
-        let mut chunks = Vec::new();
+    pub fn read_header(&self) -> std::io::Result<Vec<(u32, u8)>> {
         // Only read the first 4096 bytes
         // Where the sector position and size are stored
-        // We dont care about the timestamps for now
-        for _ in 0..32 {
-            for _ in 0..32 {
-                // Format: [3byte offset, 1byte sector size]
-                let offset = self.reader.read_u24::<BigEndian>()?;
-                let size = self.reader.read_u8()?;
-                chunks.push((offset, size as u8));
-            }
-        }
+        // Format: [3byte offset, 1byte sector size]
+        let header = self.bytes(0, SECTOR_SIZE)?;
+        Ok(header
+            .chunks_exact(4)
+            .map(|entry| (BigEndian::read_u24(entry), entry[3]))
+            .collect())
+    }
 
-        Ok(chunks)
+    // Returns when each chunk was last saved, in seconds since the unix epoch
+    // They are stored in the second 4096 bytes, in the same order as the header
+    // Chunks which were never saved have 0
+    pub fn read_timestamps(&self) -> std::io::Result<Vec<u32>> {
+        let timestamps = self.bytes(SECTOR_SIZE, SECTOR_SIZE)?;
+        Ok(timestamps
+            .chunks_exact(4)
+            .map(BigEndian::read_u32)
+            .collect())
+    }
+
+    // When the most recently saved chunk of the region was saved
+    pub fn last_modified(&self) -> std::io::Result<u32> {
+        Ok(self.read_timestamps()?.into_iter().max().unwrap_or(0))
+    }
+
+    // The chunks saved after the given time, as their index in the header
+    // The viewer only needs `last_modified`, this is checked by the tests
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn changed_since(&self, time: u32) -> std::io::Result<Vec<usize>> {
+        Ok(self
+            .read_timestamps()?
+            .into_iter()
+            .enumerate()
+            .filter(|(_, timestamp)| *timestamp > time)
+            .map(|(index, _)| index)
+            .collect())
     }
 
     // Returns the chunk nbt data uncompressed but undeserialized
-    pub fn read_chunk(&mut self, offset: u32, size: u8) -> std::io::Result<Vec<u8>> {
-        self.reader
-            .seek(SeekFrom::Start(u64::from(offset) * SECTOR_SIZE))?;
+    // index: the position of the chunk in the header
+    pub fn read_chunk(&self, index: usize, offset: u32, size: u8) -> std::io::Result<Vec<u8>> {
+        let (length, compression_type) = self.read_chunk_header(offset)?;
+        let length = u64::from(length);
+        // 4 bytes for the length must be added, and they must fit in the sectors of the chunk
+        if length == 0 || length + 4 > u64::from(size) * SECTOR_SIZE {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Chunk length is outside of its sectors",
+            ));
+        }
 
-        // This number rapresents the length in bytes with the compression_type u8
+        // The data starts after the length and the compression_type
         // We subtract one to get the length of only the compressed data
-        let _length = self.reader.read_u32::<BigEndian>()? - 1;
-        let compression_type = self.reader.read_u8()?;
-        let actual_size = u64::from(size) * SECTOR_SIZE;
+        let mut compressed_chunk = self.bytes(u64::from(offset) * SECTOR_SIZE + 5, length - 1)?;
 
-        // The actual_size - 5 is the size of the compressed data
-        // 4 bytes for the length, 1 byte for the compression_type must be subtracted
-        let mut compressed_chunk = vec![0; actual_size as usize - 5];
-        self.reader.read_exact(&mut compressed_chunk)?;
+        // Chunks bigger than 1MiB are saved in their own file, c.X.Z.mcc next to the region
+        // Then the region only contains the compression_type, with this bit set
+        if compression_type & 0x80 != 0 {
+            compressed_chunk = Cow::Owned(std::fs::read(self.external_path(index)?)?);
+        }
 
         let mut uncompressed_chunk = Vec::new();
-        match compression_type {
+        match compression_type & 0x7F {
             1 => {
                 let mut z = GzDecoder::new(&compressed_chunk[..]);
                 z.read_to_end(&mut uncompressed_chunk)?;
@@ -75,25 +212,344 @@ impl RegionFile {
                 let mut z = ZlibDecoder::new(&compressed_chunk[..]);
                 z.read_to_end(&mut uncompressed_chunk)?;
             }
-            _ => panic!("Unknown nbt compression type"),
+            // Saved without compression
+            3 => uncompressed_chunk = compressed_chunk.into_owned(),
+            4 => uncompressed_chunk = decompress_lz4(&compressed_chunk)?,
+            compression_type => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Unknown nbt compression type {}", compression_type),
+                ))
+            }
         }
 
         Ok(uncompressed_chunk)
     }
+
+    // Returns (length, compression_type) of the chunk starting at this sector
+    // The length is in bytes and counts the compression_type u8
+    fn read_chunk_header(&self, offset: u32) -> std::io::Result<(u32, u8)> {
+        let header = self.bytes(u64::from(offset) * SECTOR_SIZE, 5)?;
+        Ok((BigEndian::read_u32(&header), header[4]))
+    }
+
+    // The path of the file of a chunk saved outside of the region
+    // Its name uses the coordinates of the chunk in the world
+    fn external_path(&self, index: usize) -> std::io::Result<PathBuf> {
+        let (region_x, region_z) = parse_region_name(&self.path).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Region file name has no coordinates",
+            )
+        })?;
+        let x = region_x * REGION_SIZE as i32 + (index % REGION_SIZE) as i32;
+        let z = region_z * REGION_SIZE as i32 + (index / REGION_SIZE) as i32;
+        Ok(self.path.with_file_name(format!("c.{}.{}.mcc", x, z)))
+    }
 }
 
-// bytes: bytes buffer, start: start of the number in bits, n: length of the number in bits
-fn read_bits(bytes: &[u8], start: usize, n: usize) -> u32 {
-    let mut number = 0;
-    for j in 0..n {
-        let bit = start + j;
-        // Get a bit that forms this number (false = 0, true = 1)
-        let binary = bytes[bit / 8] & (0x01 << (bit % 8)) != 0;
-        if binary {
-            number += 2u32.pow(j as u32);
+// Chunks which need more sectors than this are saved in an external .mcc file
+const MAX_CHUNK_SECTORS: u64 = 255;
+// The location and timestamp tables
+const HEADER_SECTORS: u64 = 2;
+
+// Writing support, used to trim, fix or copy chunks
+impl RegionFile {
+    // Opens a region file for reading and writing, creating it if it doesn't exist
+    pub fn open_writable(file_name: &Path) -> std::io::Result<RegionFile> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(file_name)?;
+        // New files start with empty tables
+        if file.metadata()?.len() < HEADER_SECTORS * SECTOR_SIZE {
+            file.set_len(HEADER_SECTORS * SECTOR_SIZE)?;
+        }
+        RegionFile::open(file_name, file, false)
+    }
+
+    // Compresses and saves the nbt data of a chunk, replacing the old one
+    // index: the position of the chunk in the header
+    pub fn write_chunk(
+        &mut self,
+        index: usize,
+        chunk: &[u8],
+        compression: nbt::writer::Compression,
+    ) -> std::io::Result<()> {
+        let (compression_type, mut compressed_chunk) = match compression {
+            nbt::writer::Compression::None => (3, chunk.to_vec()),
+            nbt::writer::Compression::Gzip => {
+                let mut z = GzEncoder::new(Vec::new(), Default::default());
+                z.write_all(chunk)?;
+                (1, z.finish()?)
+            }
+            nbt::writer::Compression::Zlib => {
+                let mut z = ZlibEncoder::new(Vec::new(), Default::default());
+                z.write_all(chunk)?;
+                (2, z.finish()?)
+            }
+        };
+
+        // Chunks too big for the region are saved in their own file
+        let mut compression_type: u8 = compression_type;
+        if sectors_for(compressed_chunk.len() + 5) > MAX_CHUNK_SECTORS {
+            std::fs::write(self.external_path(index)?, &compressed_chunk)?;
+            compressed_chunk.clear();
+            compression_type |= 0x80;
+        } else {
+            self.remove_external(index)?;
+        }
+
+        // Format: [4byte length, 1byte compression_type, data], padded to the sectors
+        let mut payload = Vec::with_capacity(compressed_chunk.len() + 5);
+        payload.extend_from_slice(&(compressed_chunk.len() as u32 + 1).to_be_bytes());
+        payload.push(compression_type);
+        payload.extend_from_slice(&compressed_chunk);
+        let size = sectors_for(payload.len());
+        payload.resize((size * SECTOR_SIZE) as usize, 0);
+
+        let offset = self.allocate(index, size)?;
+        let file = self.file.get_mut().unwrap();
+        file.seek(SeekFrom::Start(offset * SECTOR_SIZE))?;
+        file.write_all(&payload)?;
+
+        self.write_location(index, offset as u32, size as u8)?;
+        self.write_timestamp(index, now())?;
+        // The file could have grown
+        self.reload()
+    }
+
+    // Removes a chunk from the region, the game will generate it again
+    pub fn delete_chunk(&mut self, index: usize) -> std::io::Result<()> {
+        self.remove_external(index)?;
+        self.write_location(index, 0, 0)?;
+        self.write_timestamp(index, 0)
+    }
+
+    // Removes the file of the chunk if it is currently saved outside of the region
+    // Regions without coordinates in their name can still be written, as long as no chunk is external
+    fn remove_external(&self, index: usize) -> std::io::Result<()> {
+        let (offset, size) = self.read_header()?[index];
+        if offset == 0 || size == 0 {
+            return Ok(());
+        }
+        let external = self
+            .read_chunk_header(offset)
+            .is_ok_and(|(_, compression_type)| compression_type & 0x80 != 0);
+        if external {
+            let external_path = self.external_path(index)?;
+            if external_path.exists() {
+                std::fs::remove_file(&external_path)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Moves the chunks next to each other, removing the free sectors between them
+    // The chunks keep their order in the file
+    pub fn compact(&mut self) -> std::io::Result<()> {
+        let mut chunks: Vec<(usize, u32, u8)> = self
+            .read_header()?
+            .into_iter()
+            .enumerate()
+            .filter(|(_, (offset, size))| *offset != 0 && *size != 0)
+            .map(|(index, (offset, size))| (index, offset, size))
+            .collect();
+        chunks.sort_by_key(|(_, offset, _)| *offset);
+
+        // Broken regions can have chunks which share some sectors
+        // So all of them are copied out before any is moved, or a moved chunk could overwrite another one
+        let sectors = chunks
+            .iter()
+            .map(|(_, offset, size)| {
+                let sectors = self.bytes(
+                    u64::from(*offset) * SECTOR_SIZE,
+                    u64::from(*size) * SECTOR_SIZE,
+                )?;
+                Ok(sectors.into_owned())
+            })
+            .collect::<std::io::Result<Vec<Vec<u8>>>>()?;
+
+        let mut next = HEADER_SECTORS;
+        for ((index, offset, size), sectors) in chunks.into_iter().zip(sectors) {
+            if u64::from(offset) != next {
+                let file = self.file.get_mut().unwrap();
+                file.seek(SeekFrom::Start(next * SECTOR_SIZE))?;
+                file.write_all(&sectors)?;
+                self.write_location(index, next as u32, size)?;
+            }
+            next += u64::from(size);
+        }
+
+        // Some systems can't resize mapped files
+        self.map = None;
+        self.file.get_mut().unwrap().set_len(next * SECTOR_SIZE)?;
+        self.reload()
+    }
+
+    // Finds the first free space big enough for the chunk, or the end of the file
+    // The sectors used by the old version of the chunk are free
+    fn allocate(&mut self, index: usize, size: u64) -> std::io::Result<u64> {
+        let header = self.read_header()?;
+        let mut used: Vec<(u64, u64)> = header
+            .iter()
+            .enumerate()
+            .filter(|(i, (offset, size))| *i != index && *offset != 0 && *size != 0)
+            .map(|(_, (offset, size))| (u64::from(*offset), u64::from(*offset) + u64::from(*size)))
+            .collect();
+        used.sort();
+
+        let mut start = HEADER_SECTORS;
+        for (used_start, used_end) in used {
+            if used_start >= start + size {
+                break;
+            }
+            start = u64::max(start, used_end);
         }
+        if start + size > 1 << 24 {
+            return Err(std::io::Error::other("The region file is full"));
+        }
+        Ok(start)
+    }
+
+    fn write_location(&mut self, index: usize, offset: u32, size: u8) -> std::io::Result<()> {
+        let file = self.file.get_mut().unwrap();
+        file.seek(SeekFrom::Start(index as u64 * 4))?;
+        file.write_all(&((offset << 8) | u32::from(size)).to_be_bytes())
+    }
+
+    fn write_timestamp(&mut self, index: usize, timestamp: u32) -> std::io::Result<()> {
+        let file = self.file.get_mut().unwrap();
+        file.seek(SeekFrom::Start(SECTOR_SIZE + index as u64 * 4))?;
+        file.write_all(&timestamp.to_be_bytes())
     }
-    number
+}
+
+// How many sectors are needed for this many bytes
+fn sectors_for(length: usize) -> u64 {
+    (length as u64).div_ceil(SECTOR_SIZE)
+}
+
+// The current time as saved in the timestamps
+fn now() -> u32 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs() as u32)
+}
+
+// LZ4 chunks use the format of lz4-java's LZ4BlockOutputStream
+// A list of blocks, each one with its header, ending with an empty block
+fn decompress_lz4(mut bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+    let invalid = |message| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+
+    let mut uncompressed = Vec::new();
+    while !bytes.is_empty() {
+        // Header: [8byte magic, 1byte method, 4byte compressed length, 4byte length, 4byte checksum]
+        if bytes.len() < 21 || &bytes[..8] != b"LZ4Block" {
+            return Err(invalid("Invalid LZ4 block header"));
+        }
+        // The lower bits of the method byte give the size of the blocks, at most 32MiB
+        let method = bytes[8] & 0xF0;
+        let block_size = 1 << (10 + (bytes[8] & 0x0F));
+        let compressed_length = LittleEndian::read_u32(&bytes[9..13]) as usize;
+        let length = LittleEndian::read_u32(&bytes[13..17]) as usize;
+        // The xxhash checksum of the uncompressed block is not checked
+        // Broken blocks are found anyway when decompressing them or parsing their nbt
+        bytes = &bytes[21..];
+
+        if length == 0 {
+            break;
+        }
+        if length > block_size {
+            return Err(invalid("LZ4 block is longer than its block size"));
+        }
+        if compressed_length > bytes.len() {
+            return Err(invalid("LZ4 block is truncated"));
+        }
+        let (block, rest) = bytes.split_at(compressed_length);
+        match method {
+            // Blocks which couldn't be compressed are saved as they are
+            0x10 => {
+                if compressed_length != length {
+                    return Err(invalid("LZ4 block has the wrong length"));
+                }
+                uncompressed.extend_from_slice(block);
+            }
+            0x20 => {
+                let start = uncompressed.len();
+                uncompressed.resize(start + length, 0);
+                let written = lz4_flex::block::decompress_into(block, &mut uncompressed[start..])
+                    .map_err(|_| invalid("Invalid LZ4 block"))?;
+                if written != length {
+                    return Err(invalid("LZ4 block has the wrong length"));
+                }
+            }
+            _ => return Err(invalid("Unknown LZ4 block method")),
+        }
+        bytes = rest;
+    }
+
+    Ok(uncompressed)
+}
+
+// The coordinates of a region from its file name (r.X.Z.mca), None for other files
+pub fn parse_region_name(name: &Path) -> Option<(i32, i32)> {
+    let sections: Vec<&str> = name.file_name()?.to_str()?.split('.').collect();
+    match sections[..] {
+        ["r", x, z, _] => Some((x.parse().ok()?, z.parse().ok()?)),
+        _ => None,
+    }
+}
+
+// longs: packed numbers, start: start of the number in bits, n: length of the number in bits
+// Bits are counted from the least significant one of each long
+// The number can start in a long and end in the next one
+fn read_bits(longs: &nbt::ArrayRef<i64>, start: usize, n: usize) -> u32 {
+    let (index, offset) = (start / 64, start % 64);
+    let mut number = longs.get(index) as u64 >> offset;
+    if offset + n > 64 {
+        number |= (longs.get(index + 1) as u64) << (64 - offset);
+    }
+    (number & ((1 << n) - 1)) as u32
+}
+
+// 20w17a (1.16) stopped splitting block states between two longs
+const VERSION_1_16: i32 = 2529;
+
+// Returns the index in the palette of each of the `count` entries of a section
+// (4096 blocks, or 64 biomes) Or None if there are not enough longs for all of them
+// Before 1.16 the indexes are packed one after the other
+// From 1.16 each long contains only whole indexes, and the bits left at its end are unused
+fn unpack_states(
+    states: &nbt::ArrayRef<i64>,
+    count: usize,
+    block_bits: usize,
+    padded: bool,
+) -> Option<Vec<usize>> {
+    let per_long = 64 / block_bits;
+    let needed = if padded {
+        count.div_ceil(per_long)
+    } else {
+        count * block_bits / 64
+    };
+    if states.len() < needed {
+        return None;
+    }
+
+    let indexes = (0..count)
+        .map(|i| {
+            // The start of this number in bits
+            let start = if padded {
+                (i / per_long) * 64 + (i % per_long) * block_bits
+            } else {
+                i * block_bits
+            };
+            read_bits(states, start, block_bits) as usize
+        })
+        .collect();
+    Some(indexes)
 }
 
 #[derive(Debug)]
@@ -106,115 +562,476 @@ pub struct ChunkSection {
     pub indexes: Vec<usize>,
 }
 
+// The biomes of a chunk
+#[derive(Debug)]
+enum ChunkBiomes {
+    // The chunk has no biomes saved (ex: McRegion chunks)
+    Missing,
+    // The biome of each column, ordered by z, then x
+    Columns(Vec<BiomeId>),
+    // The biome of each 4x4x4 cell, ordered by y, then z, then x, from the cells at min_y
+    Cells { ids: Vec<BiomeId>, min_y: i32 },
+}
+
+impl ChunkBiomes {
+    // The old numeric ids, byte arrays store the ones above 127 as negative numbers
+    fn from_ids(ids: &[i32]) -> ChunkBiomes {
+        let ids: Vec<BiomeId> = ids
+            .iter()
+            .map(|id| biome::from_id(id.rem_euclid(256)))
+            .collect();
+        match ids.len() {
+            256 => ChunkBiomes::Columns(ids),
+            // The cells of 1.15 to 1.17 chunks, which start at y = 0
+            len if len > 0 && len % 16 == 0 => ChunkBiomes::Cells { ids, min_y: 0 },
+            _ => ChunkBiomes::Missing,
+        }
+    }
+
+    // The biomes of each section from 1.18, sections without them get the default biome
+    fn from_sections(sections: &[SectionNbt]) -> ChunkBiomes {
+        let biomes: Vec<(i32, Vec<BiomeId>)> = sections
+            .iter()
+            .filter_map(|section| Some((i32::from(section.y), section.biomes.as_ref()?.biomes()?)))
+            .collect();
+        let min_section = match biomes.iter().map(|(y, _)| *y).min() {
+            Some(min_section) => min_section,
+            None => return ChunkBiomes::Missing,
+        };
+        let max_section = biomes.iter().map(|(y, _)| *y).max().unwrap();
+
+        let mut ids = vec![biome::UNKNOWN; (max_section - min_section + 1) as usize * 64];
+        for (y, section) in biomes {
+            let start = (y - min_section) as usize * 64;
+            ids[start..start + 64].copy_from_slice(&section);
+        }
+        ChunkBiomes::Cells {
+            ids,
+            min_y: min_section * SECTION_SIZE as i32,
+        }
+    }
+
+    fn get(&self, x: usize, y: i32, z: usize) -> BiomeId {
+        match self {
+            ChunkBiomes::Missing => biome::UNKNOWN,
+            ChunkBiomes::Columns(ids) => ids[z * CHUNK_SIZE + x],
+            // The cells above and below the saved ones use the closest layer
+            ChunkBiomes::Cells { ids, min_y } => {
+                let layers = (ids.len() / 16) as i32;
+                let layer = (y - min_y).div_euclid(4).clamp(0, layers - 1) as usize;
+                ids[layer * 16 + (z / 4) * 4 + x / 4]
+            }
+        }
+    }
+}
+
 // The map is:
 // HashMap<name of the block, HashMap<name of the propriety, index in the model file>>
 // It is important that graphical properties are ordered the same way as in the model file
 // So that we can search for the correct variant
 type GraphPropsMap = HashMap<String, HashMap<String, usize>>;
 
-impl ChunkSection {
-    pub fn new(section: &nbt::Compound, graphic_set: &GraphPropsMap) -> (ChunkSection, i8) {
-        let mut names = Vec::new();
-        let mut indexes = Vec::new();
-        let mut properties = Vec::new();
-        let mut graphic_props = Vec::new();
-
-        if let Some(palette) = section.get("Palette") {
-            let palette = palette.as_list().expect("Could not parse Palette as list");
-            for block in palette {
-                let block = block
-                    .as_compound()
-                    .expect("Could not parse block as Compound");
-                let name = block["Name"]
-                    .as_string()
-                    .expect("Could not parse block Name as string");
-
-                let mut prop_list = String::new();
-                let graphics = graphic_set
-                    .get(name)
-                    .unwrap_or_else(||
-                        panic!("Error reading the graphic properties for block {}, are you using an old minecraft version for this world?", name)
-                    );
-                // The list of graphical properties, ordered the same way as in the blockstates files
-                let mut graphic_list = vec![String::new(); graphics.len()];
-                if let Some(block_properties) = &block.get("Properties") {
-                    let block_properties = block_properties
-                        .as_compound()
-                        .expect("Could not parse block_properties as Compound");
-                    for (key, value) in block_properties {
-                        // This format is convinient when searching the block variant
-                        // In the blockstate json files
-                        let text = format!(
-                            "{}={}",
-                            key,
-                            value
-                                .as_string()
-                                .expect("Could not parse block value as String")
-                        );
-                        prop_list.push_str(&text);
-                        // If this property is in the list of graphical properties add it in the right position
-                        // To replicate the same order as in the blockstate file
-                        if let Some(index) = graphics.get(key) {
-                            graphic_list[*index] = text;
-                        }
-                    }
+// 21w43a (1.18) moved the sections out of `Level`
+// And their palette and block states in a `block_states` compound
+const VERSION_1_18: i32 = 2844;
 
-                    // Remove the last ',' character
-                    prop_list.pop();
-                }
+// The parts of the chunk nbt used to render it
+// Strings are borrowed from the parsed tags
+#[derive(Deserialize)]
+pub struct ChunkNbt<'a> {
+    #[serde(rename = "DataVersion", default)]
+    data_version: i32,
+    // Before 1.18
+    #[serde(rename = "Level", borrow)]
+    level: Option<LevelNbt<'a>>,
+    // From 1.18
+    #[serde(borrow, default)]
+    sections: Vec<SectionNbt<'a>>,
+    #[serde(rename = "Heightmaps", borrow)]
+    heightmaps: Option<HeightmapsNbt<'a>>,
+    // The lowest section of the world
+    #[serde(rename = "yPos", default)]
+    min_section: i32,
+}
 
-                // Join the properties in a single string, in the same format as `prop_list`
-                let mut graphic_list: String =
-                    graphic_list.iter_mut().map(|x| format!("{},", x)).collect();
-                // Remove the last ',' character
-                graphic_list.pop();
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct LevelNbt<'a> {
+    #[serde(borrow)]
+    sections: Vec<SectionNbt<'a>>,
+    // The numeric id of the biome of each column (256 ids, a byte array before 1.13)
+    // Or from 1.15 of each 4x4x4 cell (1024 ids, from the bottom of the world)
+    biomes: Option<Vec<i32>>,
+    // From 1.13
+    #[serde(borrow)]
+    heightmaps: Option<HeightmapsNbt<'a>>,
+    // Before 1.13, the height above the highest block which stops light, ordered by z, then x
+    #[serde(rename = "HeightMap")]
+    legacy_heightmap: Option<Vec<i32>>,
+}
+
+impl<'a> ChunkNbt<'a> {
+    fn sections(&self) -> &[SectionNbt<'a>] {
+        if self.data_version >= VERSION_1_18 {
+            &self.sections
+        } else {
+            self.level.as_ref().map_or(&[], |level| &level.sections)
+        }
+    }
 
-                names.push(name.to_owned());
-                properties.push(prop_list);
-                graphic_props.push(graphic_list);
+    // The height above the highest block of each column, ordered by z, then x
+    // None if the chunk doesn't have this heightmap (ex: chunks from before 1.13, but motion_blocking)
+    fn heightmap(&self, heightmap: Heightmap) -> Option<Vec<i32>> {
+        let (heightmaps, min_y, height) = if self.data_version >= VERSION_1_18 {
+            // Every section of the world is saved with its block states
+            // Unlike the ones just below and above it, which only have light
+            let sections = self
+                .sections
+                .iter()
+                .filter(|section| section.container.is_some())
+                .count();
+            (
+                self.heightmaps.as_ref()?,
+                self.min_section * SECTION_SIZE as i32,
+                sections * SECTION_SIZE,
+            )
+        } else {
+            let level = self.level.as_ref()?;
+            match &level.heightmaps {
+                Some(heightmaps) => (heightmaps, 0, 256),
+                // The old heightmap skips glass and the other blocks which let light through
+                // Which is close enough to motion_blocking, but would hide them in the others
+                None => {
+                    return match heightmap {
+                        Heightmap::MotionBlocking => level
+                            .legacy_heightmap
+                            .clone()
+                            .filter(|heights| heights.len() == 256),
+                        _ => None,
+                    }
+                }
             }
+        };
+        let data = match heightmap {
+            Heightmap::WorldSurface => heightmaps.world_surface,
+            Heightmap::MotionBlocking => heightmaps.motion_blocking,
+            Heightmap::OceanFloor => heightmaps.ocean_floor,
+        }?;
 
-            // If 'Palette' was in the nbt, BlockStates will also be there
-            // In future versions of Rust use the `if let` chain syntax
-            let states = &section["BlockStates"]
-                .as_i64_vec()
-                .expect("Could not parse BlockStates as i64 vec");
-            // Trasform the i64 array in a LittleEndian byte array
-            let states: Vec<u8> = states
+        // The heights use the bits needed for the height of the world, ceil(log2(height + 1))
+        // Heightmaps with a different number of longs are from another height, and are skipped
+        let bits = (usize::BITS - height.leading_zeros()) as usize;
+        let padded = self.data_version >= VERSION_1_16;
+        let longs = if padded {
+            256_usize.div_ceil(64 / usize::max(bits, 1))
+        } else {
+            (256 * bits).div_ceil(64)
+        };
+        if height == 0 || data.len() != longs {
+            return None;
+        }
+        let heights = unpack_states(&data, 256, bits, padded)?;
+        Some(
+            heights
                 .iter()
-                .flat_map(|state| {
-                    let mut buf = [0; 8];
-                    LittleEndian::write_i64(&mut buf, *state);
-                    buf.to_vec()
-                })
-                .collect();
-
-            // There are always 4096 different blocks per section (a section is a cube of blocks 16x16x16 = 4096)
-            // So we can calculate the number of bits used for each block like this
-            let block_bits = states.len() * 8 / 4096;
-            let block_bits = usize::max(block_bits, 4);
-            for i in 0..4096 {
-                // The start of this number in bits is `i * block_bits`
-                let number = read_bits(&states, i * block_bits, block_bits) as usize;
-                indexes.push(number);
+                .map(|height| *height as i32 + min_y)
+                .collect(),
+        )
+    }
+}
+
+// The heightmaps which can be used to find where to start rendering each column
+// Each one gives the height above the highest block of some kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Heightmap {
+    // Any block except air
+    WorldSurface,
+    // Blocks which stop the movement of entities (not plants, torches, ...), and fluids
+    MotionBlocking,
+    // Like MotionBlocking, but without fluids
+    OceanFloor,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+struct HeightmapsNbt<'a> {
+    #[serde(borrow)]
+    world_surface: Option<nbt::ArrayRef<'a, i64>>,
+    #[serde(borrow)]
+    motion_blocking: Option<nbt::ArrayRef<'a, i64>>,
+    #[serde(borrow)]
+    ocean_floor: Option<nbt::ArrayRef<'a, i64>>,
+}
+
+#[derive(Deserialize)]
+pub struct SectionNbt<'a> {
+    #[serde(rename = "Y")]
+    y: i8,
+    // Before 1.18
+    #[serde(rename = "Palette", borrow)]
+    palette: Option<Vec<BlockNbt<'a>>>,
+    #[serde(rename = "BlockStates", borrow)]
+    block_states: Option<nbt::ArrayRef<'a, i64>>,
+    // From 1.18
+    #[serde(rename = "block_states", borrow)]
+    container: Option<BlockStatesNbt<'a>>,
+    // Before 1.13, the numeric id of each block and 4 more bits for ids above 255
+    #[serde(rename = "Blocks", borrow)]
+    legacy_blocks: Option<nbt::ArrayRef<'a, i8>>,
+    #[serde(rename = "Add", borrow)]
+    legacy_add: Option<nbt::ArrayRef<'a, i8>>,
+    // The metadata of each block (color, facing, ...), 4 bits each
+    #[serde(rename = "Data", borrow)]
+    legacy_data: Option<nbt::ArrayRef<'a, i8>>,
+    // From 1.18, the biome of each 4x4x4 cell of the section
+    #[serde(borrow)]
+    biomes: Option<BiomesNbt<'a>>,
+}
+
+#[derive(Deserialize)]
+struct BlockStatesNbt<'a> {
+    #[serde(borrow)]
+    palette: Vec<BlockNbt<'a>>,
+    // Missing when there is only one block in the palette
+    #[serde(borrow)]
+    data: Option<nbt::ArrayRef<'a, i64>>,
+}
+
+// Like the block states, but with the names of the biomes in the palette
+#[derive(Deserialize)]
+struct BiomesNbt<'a> {
+    #[serde(borrow)]
+    palette: Vec<&'a str>,
+    #[serde(borrow)]
+    data: Option<nbt::ArrayRef<'a, i64>>,
+}
+
+impl<'a> BiomesNbt<'a> {
+    // The biome of each of the 64 cells, ordered by y, then z, then x
+    fn biomes(&self) -> Option<Vec<BiomeId>> {
+        let palette: Vec<BiomeId> = self
+            .palette
+            .iter()
+            .map(|name| biome::from_name(name))
+            .collect();
+        let indexes = match self.data {
+            // Unlike blocks, biomes use exactly the bits needed for the biggest index
+            Some(data) => {
+                let max_index = palette.len().checked_sub(1)?;
+                let bits = (usize::BITS - max_index.leading_zeros()) as usize;
+                unpack_states(&data, 64, usize::max(bits, 1), true)?
             }
+            None => vec![0; 64],
+        };
+        indexes.iter().map(|i| palette.get(*i).copied()).collect()
+    }
+}
+
+type Blocks<'s, 'a> = (&'s [BlockNbt<'a>], Option<nbt::ArrayRef<'a, i64>>);
+
+impl<'a> SectionNbt<'a> {
+    // The palette and the block states of the section, None if it has no blocks
+    fn blocks(&self, data_version: i32) -> Option<Blocks<'_, 'a>> {
+        if data_version >= VERSION_1_18 {
+            let container = self.container.as_ref()?;
+            Some((&container.palette, container.data))
+        } else {
+            Some((self.palette.as_ref()?, self.block_states))
         }
+    }
 
-        // Check that there are no indexes which go out of bounds
-        if let Some(max) = indexes.iter().max() {
-            assert!(*max == names.len() - 1);
+    // The numeric id and the metadata of each block, None if the arrays are missing or broken
+    fn legacy_blocks(&self) -> Option<impl Fn(usize) -> (u16, u8) + '_> {
+        let blocks = self.legacy_blocks.filter(|blocks| blocks.len() == 4096)?;
+        let add = self.legacy_add.filter(|add| add.len() == 2048);
+        let data = self.legacy_data.filter(|data| data.len() == 2048);
+        Some(move |i: usize| {
+            let id = u16::from(blocks.get(i) as u8) | u16::from(nibble(add, i)) << 8;
+            (id, nibble(data, i))
+        })
+    }
+}
+
+// Nibble arrays store two numbers in each byte, the first one in the lower bits
+fn nibble(array: Option<nbt::ArrayRef<i8>>, i: usize) -> u8 {
+    match array {
+        Some(array) => (array.get(i / 2) as u8 >> ((i % 2) * 4)) & 0xF,
+        None => 0,
+    }
+}
+
+// How many blocks is a McRegion chunk high
+const MCREGION_HEIGHT: usize = 128;
+
+// The parts of a McRegion chunk used to render it
+#[derive(Deserialize)]
+struct McRegionChunkNbt<'a> {
+    #[serde(rename = "Level", borrow)]
+    level: McRegionLevelNbt<'a>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct McRegionLevelNbt<'a> {
+    // The numeric id of each block, from the bottom to the top of each column
+    #[serde(borrow)]
+    blocks: nbt::ArrayRef<'a, i8>,
+    // The metadata of each block, 4 bits each
+    #[serde(borrow)]
+    data: nbt::ArrayRef<'a, i8>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BlockNbt<'a> {
+    name: &'a str,
+    // The properties keep the order they are saved in
+    #[serde(borrow, default)]
+    properties: IndexMap<&'a str, &'a str>,
+}
+
+// Blocks without graphic properties are rendered as air, each name is reported only once
+fn warn_unknown_block(name: &str) {
+    static WARNED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    let mut warned = WARNED.lock().unwrap();
+    if !warned.iter().any(|warned| warned == name) {
+        println!(
+            "Unknown block {}, it is rendered as air. Are you using an older minecraft version than the world's?",
+            name
+        );
+        warned.push(name.to_owned());
+    }
+}
+
+impl ChunkSection {
+    pub fn new(
+        section: &SectionNbt,
+        data_version: i32,
+        graphic_set: &GraphPropsMap,
+    ) -> (ChunkSection, i8) {
+        let mut chunk_section = ChunkSection {
+            names: Vec::new(),
+            properties: Vec::new(),
+            graphic_props: Vec::new(),
+            indexes: Vec::new(),
+        };
+
+        // Before 1.13 (17w47a) sections saved numeric ids instead of a palette
+        // Chunks that old can miss the DataVersion, so the saved arrays are checked instead
+        if section.legacy_blocks.is_some() {
+            if let Some(get) = section.legacy_blocks() {
+                chunk_section = ChunkSection::from_legacy(get, graphic_set);
+            }
+        } else if let Some((palette, states)) = section.blocks(data_version) {
+            chunk_section.add_palette(palette, graphic_set);
+            // An empty palette has no blocks to index, so the section is left empty
+            match (states, palette.len().checked_sub(1)) {
+                (Some(states), Some(max_index)) => {
+                    // Each block uses the bits needed for the biggest index in the palette, at least 4
+                    let block_bits = (usize::BITS - max_index.leading_zeros()) as usize;
+                    let block_bits = usize::max(block_bits, 4);
+                    let padded = data_version >= VERSION_1_16;
+                    chunk_section.indexes =
+                        unpack_states(&states, 4096, block_bits, padded).unwrap_or_default();
+                }
+                // The whole section is made of the only block in the palette
+                (None, Some(_)) => chunk_section.indexes = vec![0; 4096],
+                (_, None) => {}
+            }
         }
 
-        let y_index = section["Y"].as_i8().expect("Could not parse Y as i8");
-        (
-            ChunkSection {
-                names,
-                properties,
-                graphic_props,
-                indexes,
-            },
-            *y_index,
-        )
+        // Sections with broken block states are left empty
+        let broken = {
+            let ChunkSection { names, indexes, .. } = &chunk_section;
+            !names.is_empty() && (indexes.is_empty() || indexes.iter().any(|i| *i >= names.len()))
+        };
+        if broken {
+            println!("Broken block states in section {}", section.y);
+            chunk_section.names.clear();
+            chunk_section.properties.clear();
+            chunk_section.graphic_props.clear();
+            chunk_section.indexes.clear();
+        }
+
+        (chunk_section, section.y)
+    }
+
+    // Builds a section from the numeric id and the metadata of each block
+    // They are converted to a palette of 1.13 block states
+    fn from_legacy<F>(get: F, graphic_set: &GraphPropsMap) -> ChunkSection
+    where
+        F: Fn(usize) -> (u16, u8),
+    {
+        let (states, indexes) = legacy::flatten(get);
+        let palette: Vec<BlockNbt> = states
+            .iter()
+            .map(|(name, props)| BlockNbt {
+                name,
+                properties: props
+                    .iter()
+                    .map(|(key, value)| (*key, &value[..]))
+                    .collect(),
+            })
+            .collect();
+
+        let mut chunk_section = ChunkSection {
+            names: Vec::new(),
+            properties: Vec::new(),
+            graphic_props: Vec::new(),
+            indexes,
+        };
+        chunk_section.add_palette(&palette, graphic_set);
+        chunk_section
+    }
+
+    // Adds the names and the properties of the blocks in the palette
+    fn add_palette(&mut self, palette: &[BlockNbt], graphic_set: &GraphPropsMap) {
+        for block in palette {
+            let name = block.name;
+
+            let graphics = match graphic_set.get(name) {
+                Some(graphics) => graphics,
+                // Keep the position in the palette, so that the indexes stay valid
+                None => {
+                    warn_unknown_block(name);
+                    self.names.push(EMPTY_BLOCK.to_owned());
+                    self.properties.push(String::new());
+                    self.graphic_props.push(String::new());
+                    continue;
+                }
+            };
+
+            let mut prop_list = String::new();
+            // The list of graphical properties, ordered the same way as in the blockstates files
+            let mut graphic_list = vec![String::new(); graphics.len()];
+            if !block.properties.is_empty() {
+                for (key, value) in &block.properties {
+                    // This format is convinient when searching the block variant
+                    // In the blockstate json files
+                    let text = format!("{}={}", key, value);
+                    prop_list.push_str(&text);
+                    prop_list.push(',');
+                    // If this property is in the list of graphical properties add it in the right position
+                    // To replicate the same order as in the blockstate file
+                    if let Some(index) = graphics.get(*key) {
+                        graphic_list[*index] = text;
+                    }
+                }
+
+                // Remove the last ',' character
+                prop_list.pop();
+            }
+
+            // Join the properties in a single string, in the same format as `prop_list`
+            let mut graphic_list: String =
+                graphic_list.iter_mut().map(|x| format!("{},", x)).collect();
+            // Remove the last ',' character
+            graphic_list.pop();
+
+            self.names.push(name.to_owned());
+            self.properties.push(prop_list);
+            self.graphic_props.push(graphic_list);
+        }
     }
 
     fn get_index(&self, x: usize, y: usize, z: usize) -> usize {
@@ -242,58 +1059,183 @@ impl ChunkSection {
 
 #[derive(Debug)]
 pub struct Chunk {
+    // The sections from the lowest one, at `min_section`, upwards
     sections: Vec<Option<ChunkSection>>,
+    min_section: i32,
+    biomes: ChunkBiomes,
+    // The heightmap used to start rendering the columns, if the chunk has it
+    heights: Option<Vec<i32>>,
 }
 
 impl Chunk {
-    pub fn new(chunk_nbt: &nbt::Compound, graphic_set: &GraphPropsMap) -> Chunk {
-        // Add the sections to the chunk
-        let mut sections: Vec<Option<ChunkSection>> = (0..16).map(|_| None).collect();
-        let sections_nbt = chunk_nbt["Sections"]
-            .as_list()
-            .expect("Could not parse Sections as list");
-        for section_nbt in sections_nbt {
-            let section_nbt = section_nbt
-                .as_compound()
-                .expect("Could not parse section as Compount");
-            let (section, y) = ChunkSection::new(section_nbt, graphic_set);
-            // Sometimes there are chunks with the index of -1
-            // Which are completely empty
-            if y != -1 && !section.names.is_empty() {
-                sections[y as usize] = Some(section);
+    pub fn new(chunk: &ChunkNbt, heightmap: Heightmap, graphic_set: &GraphPropsMap) -> Chunk {
+        let mut loaded = Vec::new();
+        for section_nbt in chunk.sections() {
+            let (section, y) = ChunkSection::new(section_nbt, chunk.data_version, graphic_set);
+            // Some sections (ex: the ones below and above the world with only light data)
+            // Are completely empty
+            if !section.names.is_empty() {
+                loaded.push((i32::from(y), section));
             }
         }
 
-        Chunk { sections }
+        let mut loaded = Chunk::from_sections(loaded);
+        loaded.biomes = if chunk.data_version >= VERSION_1_18 {
+            ChunkBiomes::from_sections(chunk.sections())
+        } else {
+            match chunk.level.as_ref().and_then(|level| level.biomes.as_ref()) {
+                Some(ids) => ChunkBiomes::from_ids(ids),
+                None => ChunkBiomes::Missing,
+            }
+        };
+        loaded.heights = chunk.heightmap(heightmap);
+        loaded
     }
 
-    pub fn get_block(&self, x: usize, y: usize, z: usize) -> &str {
-        let index = y / SECTION_SIZE;
-        if let Some(section) = &self.sections[index] {
-            section.get_block(x, y % SECTION_SIZE, z)
+    // Splits the blocks of a McRegion chunk in 8 sections, like the ones of Anvil chunks
+    fn from_mcregion(level: &McRegionLevelNbt, graphic_set: &GraphPropsMap) -> Chunk {
+        let (blocks, data) = (level.blocks, level.data);
+        let height = MCREGION_HEIGHT;
+        if blocks.len() != CHUNK_SIZE * CHUNK_SIZE * height || data.len() != blocks.len() / 2 {
+            println!("Broken blocks in McRegion chunk");
+            return Chunk::from_sections(Vec::new());
+        }
+
+        let mut loaded = Vec::new();
+        for y in 0..height / SECTION_SIZE {
+            // The blocks are ordered by x, then z, then y, the opposite of sections
+            let get = |i: usize| {
+                let (section_y, z, x) = (i / 256, (i / 16) % 16, i % 16);
+                let index = (y * SECTION_SIZE + section_y) + z * height + x * height * CHUNK_SIZE;
+                (
+                    u16::from(blocks.get(index) as u8),
+                    nibble(Some(data), index),
+                )
+            };
+            // Sections made only of air are not saved in Anvil chunks either
+            if (0..4096).any(|i| get(i).0 != 0) {
+                loaded.push((y as i32, ChunkSection::from_legacy(get, graphic_set)));
+            }
+        }
+        Chunk::from_sections(loaded)
+    }
+
+    // Takes the loaded sections with their height
+    fn from_sections(loaded: Vec<(i32, ChunkSection)>) -> Chunk {
+        let min_section = loaded.iter().map(|(y, _)| *y).min().unwrap_or(0);
+        let max_section = loaded.iter().map(|(y, _)| *y).max().unwrap_or(-1);
+        let mut sections: Vec<Option<ChunkSection>> =
+            (min_section..=max_section).map(|_| None).collect();
+        for (y, section) in loaded {
+            sections[(y - min_section) as usize] = Some(section);
+        }
+
+        Chunk {
+            sections,
+            min_section,
+            biomes: ChunkBiomes::Missing,
+            heights: None,
+        }
+    }
+
+    // The blocks from min_y (included) to max_y (excluded) can be non empty
+    pub fn height_range(&self) -> (i32, i32) {
+        let min_y = self.min_section * SECTION_SIZE as i32;
+        (min_y, min_y + (self.sections.len() * SECTION_SIZE) as i32)
+    }
+
+    fn get_section(&self, y: i32) -> Option<&ChunkSection> {
+        let index = y.div_euclid(SECTION_SIZE as i32) - self.min_section;
+        if index < 0 {
+            return None;
+        }
+        self.sections.get(index as usize)?.as_ref()
+    }
+
+    pub fn get_block(&self, x: usize, y: i32, z: usize) -> &str {
+        if let Some(section) = self.get_section(y) {
+            section.get_block(x, y.rem_euclid(SECTION_SIZE as i32) as usize, z)
         } else {
             EMPTY_BLOCK
         }
     }
 
     #[allow(dead_code)]
-    pub fn get_prop(&self, x: usize, y: usize, z: usize) -> &str {
-        let index = y / SECTION_SIZE;
-        if let Some(section) = &self.sections[index] {
-            section.get_prop(x, y % SECTION_SIZE, z)
+    pub fn get_prop(&self, x: usize, y: i32, z: usize) -> &str {
+        if let Some(section) = self.get_section(y) {
+            section.get_prop(x, y.rem_euclid(SECTION_SIZE as i32) as usize, z)
         } else {
             ""
         }
     }
 
-    pub fn get_gprop(&self, x: usize, y: usize, z: usize) -> &str {
-        let index = y / SECTION_SIZE;
-        if let Some(section) = &self.sections[index] {
-            section.get_gprop(x, y % SECTION_SIZE, z)
+    pub fn get_gprop(&self, x: usize, y: i32, z: usize) -> &str {
+        if let Some(section) = self.get_section(y) {
+            section.get_gprop(x, y.rem_euclid(SECTION_SIZE as i32) as usize, z)
         } else {
             ""
         }
     }
+
+    pub fn get_biome(&self, x: usize, y: i32, z: usize) -> BiomeId {
+        self.biomes.get(x, y, z)
+    }
+
+    // The height above the highest block of the column in the heightmap, if the chunk has one
+    pub fn get_height(&self, x: usize, z: usize) -> Option<i32> {
+        Some(self.heights.as_ref()?[z * CHUNK_SIZE + x])
+    }
+}
+
+// The parts of the chunk nbt used to render it
+// Everything else (entities, lighting, ...) is skipped while parsing
+const CHUNK_PATHS: &[&str] = &[
+    "DataVersion",
+    "Level.Sections.Y",
+    "Level.Sections.Palette",
+    "Level.Sections.BlockStates",
+    "Level.Sections.Blocks",
+    "Level.Sections.Add",
+    "Level.Sections.Data",
+    "Level.Biomes",
+    "Level.Heightmaps",
+    "Level.HeightMap",
+    "sections.Y",
+    "sections.block_states",
+    "sections.biomes",
+    "Heightmaps",
+    "yPos",
+];
+
+const MCREGION_PATHS: &[&str] = &["Level.Blocks", "Level.Data"];
+
+// Parses the nbt data of a chunk, keeping only what is needed to render it
+// The block states are read directly from the uncompressed bytes
+fn parse_chunk(
+    bytes: &[u8],
+    filter: &nbt::Filter,
+    format: RegionFormat,
+    heightmap: Heightmap,
+    graphic_set: &GraphPropsMap,
+) -> nbt::Result<Chunk> {
+    let tags = nbt::NBTParser::new(bytes).read_compound_filtered(filter)?;
+
+    // The chunk is stored in the root compound, which has an empty name
+    let root = match tags.as_compound().and_then(|tags| tags.get("")) {
+        Some(root) => root,
+        None => return Err(serde::de::Error::missing_field("")),
+    };
+
+    match format {
+        RegionFormat::Anvil => {
+            let chunk_nbt: ChunkNbt = nbt::de::from_tag_ref(root)?;
+            Ok(Chunk::new(&chunk_nbt, heightmap, graphic_set))
+        }
+        RegionFormat::McRegion => {
+            let chunk_nbt: McRegionChunkNbt = nbt::de::from_tag_ref(root)?;
+            Ok(Chunk::from_mcregion(&chunk_nbt.level, graphic_set))
+        }
+    }
 }
 
 pub struct Region {
@@ -311,39 +1253,65 @@ impl Region {
         }
     }
 
+    // The heightmap of each chunk is loaded, to know where to start rendering the columns
+    // With mmap the file is mapped in memory, see `RegionFile::new_mapped`
     pub fn from_file(
-        file_name: &std::path::Path,
+        file_name: &Path,
+        mmap: bool,
+        heightmap: Heightmap,
         graphic_set: &GraphPropsMap,
     ) -> std::io::Result<Region> {
-        let mut region_nbt = RegionFile::new(file_name)?;
+        Region::from_file_filtered(file_name, mmap, heightmap, graphic_set, |_, _| true)
+    }
+
+    // Only loads the chunks for which keep(x, z) is true, with x and z from 0 to REGION_SIZE
+    pub fn from_file_filtered<F>(
+        file_name: &Path,
+        mmap: bool,
+        heightmap: Heightmap,
+        graphic_set: &GraphPropsMap,
+        keep: F,
+    ) -> std::io::Result<Region>
+    where
+        F: Fn(usize, usize) -> bool + Sync,
+    {
+        let region_nbt = if mmap {
+            RegionFile::new_mapped(file_name)?
+        } else {
+            RegionFile::new(file_name)?
+        };
         let chunks_nbt = region_nbt.read_header()?;
+        let format = region_nbt.format();
+        let filter = nbt::Filter::new(match format {
+            RegionFormat::Anvil => CHUNK_PATHS,
+            RegionFormat::McRegion => MCREGION_PATHS,
+        });
 
-        let mut chunks = Vec::new();
-        for &(offset, size) in &chunks_nbt {
-            if offset != 0 && size != 0 {
-                if let Ok(chunk) = region_nbt.read_chunk(offset, size) {
-                    let mut parser = nbt::NBTParser::new(chunk);
-
-                    let tags = parser.read_compound()?;
-                    let tags = tags
-                        .as_compound()
-                        .expect("Could not read nbt tags from file");
-                    let level = tags[""]
-                        .as_compound()
-                        .expect("Could not parse tags[\"\"] as compound")["Level"]
-                        .as_compound()
-                        .expect("Could not read Level from nbt tags");
-
-                    let chunk = Chunk::new(&level, graphic_set);
-                    chunks.push(Some(chunk));
-                } else {
-                    println!("Chunk error");
-                    chunks.push(None);
+        // The chunks are read and parsed in parallel
+        let chunks = chunks_nbt
+            .par_iter()
+            .enumerate()
+            .map(|(index, &(offset, size))| {
+                if offset == 0 || size == 0 || !keep(index % REGION_SIZE, index / REGION_SIZE) {
+                    return None;
                 }
-            } else {
-                chunks.push(None);
-            }
-        }
+                // A broken chunk shouldn't stop the whole region from loading
+                let chunk = region_nbt
+                    .read_chunk(index, offset, size)
+                    .map_err(|err| err.to_string())
+                    .and_then(|chunk| {
+                        parse_chunk(&chunk, &filter, format, heightmap, graphic_set)
+                            .map_err(|err| err.to_string())
+                    });
+                match chunk {
+                    Ok(chunk) => Some(chunk),
+                    Err(err) => {
+                        println!("Chunk error in {}: {}", file_name.display(), err);
+                        None
+                    }
+                }
+            })
+            .collect();
 
         Ok(Region::new(chunks))
     }
@@ -352,7 +1320,21 @@ impl Region {
         (z / CHUNK_SIZE) * REGION_SIZE + (x / CHUNK_SIZE)
     }
 
-    pub fn get_block(&self, x: usize, y: usize, z: usize) -> &str {
+    // The heights from which the chunks of this region can have blocks
+    // From min_y (included) to max_y (excluded)
+    // It goes through every chunk, so it should be computed once per region, not per column
+    pub fn height_range(&self) -> (i32, i32) {
+        self.chunks
+            .iter()
+            .flatten()
+            .map(Chunk::height_range)
+            .filter(|(min_y, max_y)| min_y < max_y)
+            .fold((0, 0), |(min_y, max_y), (chunk_min, chunk_max)| {
+                (i32::min(min_y, chunk_min), i32::max(max_y, chunk_max))
+            })
+    }
+
+    pub fn get_block(&self, x: usize, y: i32, z: usize) -> &str {
         let index = self.get_index(x, z);
         if let Some(chunk) = &self.chunks[index] {
             chunk.get_block(x % CHUNK_SIZE, y, z % CHUNK_SIZE)
@@ -362,7 +1344,7 @@ impl Region {
     }
 
     #[allow(dead_code)]
-    pub fn get_prop(&self, x: usize, y: usize, z: usize) -> &str {
+    pub fn get_prop(&self, x: usize, y: i32, z: usize) -> &str {
         let index = self.get_index(x, z);
         if let Some(chunk) = &self.chunks[index] {
             chunk.get_prop(x % CHUNK_SIZE, y, z % CHUNK_SIZE)
@@ -371,7 +1353,7 @@ impl Region {
         }
     }
 
-    pub fn get_gprop(&self, x: usize, y: usize, z: usize) -> &str {
+    pub fn get_gprop(&self, x: usize, y: i32, z: usize) -> &str {
         let index = self.get_index(x, z);
         if let Some(chunk) = &self.chunks[index] {
             chunk.get_gprop(x % CHUNK_SIZE, y, z % CHUNK_SIZE)
@@ -379,4 +1361,496 @@ impl Region {
             ""
         }
     }
+
+    pub fn get_biome(&self, x: usize, y: i32, z: usize) -> BiomeId {
+        let index = self.get_index(x, z);
+        if let Some(chunk) = &self.chunks[index] {
+            chunk.get_biome(x % CHUNK_SIZE, y, z % CHUNK_SIZE)
+        } else {
+            biome::UNKNOWN
+        }
+    }
+
+    // The height above the highest block of the column, None if its chunk has no heightmap
+    pub fn get_height(&self, x: usize, z: usize) -> Option<i32> {
+        self.chunks[self.get_index(x, z)]
+            .as_ref()?
+            .get_height(x % CHUNK_SIZE, z % CHUNK_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nbt::writer::{self, Compression};
+    use crate::nbt::{Compound, NBTParser, Tag};
+
+    // Packs the indexes the way minecraft does, before or from 1.16
+    fn pack(indexes: &[usize], bits: usize, padded: bool) -> Vec<i64> {
+        let mut longs = Vec::new();
+        for (i, index) in indexes.iter().enumerate() {
+            let start = if padded {
+                let per_long = 64 / bits;
+                (i / per_long) * 64 + (i % per_long) * bits
+            } else {
+                i * bits
+            };
+            let (long, offset) = (start / 64, start % 64);
+            longs.resize(long + 2, 0u64);
+            longs[long] |= (*index as u64) << offset;
+            if offset + bits > 64 {
+                longs[long + 1] |= (*index as u64) >> (64 - offset);
+            }
+        }
+        // Only the longs that contain some bits are saved
+        let bits_used = if padded {
+            indexes.len().div_ceil(64 / bits) * 64
+        } else {
+            indexes.len() * bits
+        };
+        longs.truncate(bits_used.div_ceil(64));
+        longs.into_iter().map(|long| long as i64).collect()
+    }
+
+    // The saved bytes of a compound, which the nbt references borrow from
+    fn to_nbt(comp: Compound) -> Vec<u8> {
+        writer::to_bytes("", &comp, Compression::None).unwrap()
+    }
+
+    #[test]
+    fn bit_widths() {
+        for bits in 4..=12 {
+            let indexes: Vec<usize> = (0..4096).map(|i| (i * 7 + i / 3) % (1 << bits)).collect();
+            for padded in [false, true] {
+                let mut comp = Compound::new();
+                let longs = pack(&indexes, bits, padded);
+                comp.insert("states".to_owned(), Tag::LongArray(longs));
+                let bytes = to_nbt(comp);
+                let root = NBTParser::new(&bytes).read_compound().unwrap();
+                let states = root.as_compound().unwrap()[""].as_compound().unwrap()["states"]
+                    .as_i64_array()
+                    .unwrap();
+
+                let unpacked = unpack_states(&states, 4096, bits, padded);
+                assert_eq!(unpacked.as_ref(), Some(&indexes), "{} bits", bits);
+            }
+        }
+    }
+
+    #[test]
+    fn missing_states() {
+        let mut comp = Compound::new();
+        comp.insert("states".to_owned(), Tag::LongArray(vec![0; 255]));
+        let bytes = to_nbt(comp);
+        let root = NBTParser::new(&bytes).read_compound().unwrap();
+        let states = root.as_compound().unwrap()[""].as_compound().unwrap()["states"]
+            .as_i64_array()
+            .unwrap();
+        assert_eq!(unpack_states(&states, 4096, 4, false), None);
+        assert_eq!(unpack_states(&states, 4096, 4, true), None);
+    }
+
+    // Appends an LZ4 block in the format of lz4-java, without its checksum
+    fn push_lz4_block(stream: &mut Vec<u8>, method: u8, block: &[u8], length: u32) {
+        stream.extend_from_slice(b"LZ4Block");
+        stream.push(method);
+        stream.extend_from_slice(&(block.len() as u32).to_le_bytes());
+        stream.extend_from_slice(&length.to_le_bytes());
+        stream.extend_from_slice(&[0; 4]);
+        stream.extend_from_slice(block);
+    }
+
+    #[test]
+    fn lz4_blocks() {
+        let data: Vec<u8> = (0..5000).map(|i| (i % 7) as u8).collect();
+        let mut stream = Vec::new();
+        let compressed = lz4_flex::block::compress(&data[..3000]);
+        push_lz4_block(&mut stream, 0x22, &compressed, 3000);
+        push_lz4_block(&mut stream, 0x12, &data[3000..], 2000);
+        push_lz4_block(&mut stream, 0x12, &[], 0);
+        assert_eq!(decompress_lz4(&stream).unwrap(), data);
+        assert!(decompress_lz4(&stream[..30]).is_err());
+
+        // The length is bigger than the 4KiB blocks of this stream
+        let mut stream = Vec::new();
+        push_lz4_block(&mut stream, 0x22, &compressed, 1 << 31);
+        assert!(decompress_lz4(&stream).is_err());
+
+        // An uncompressed block must be as long as its declared length
+        let mut stream = Vec::new();
+        push_lz4_block(&mut stream, 0x12, &data[..2000], 1999);
+        assert!(decompress_lz4(&stream).is_err());
+    }
+
+    #[test]
+    fn timestamps() {
+        let (folder, region) = new_region("timestamps");
+        drop(region);
+        // A region without chunks, with only the table of timestamps filled
+        let mut bytes = vec![0; 2 * SECTOR_SIZE as usize];
+        for (index, time) in [(5, 100), (7, 200), (1023, 300)] {
+            let start = SECTOR_SIZE as usize + index * 4;
+            BigEndian::write_u32(&mut bytes[start..start + 4], time);
+        }
+        let file_name = folder.join("r.-1.2.mca");
+        std::fs::write(&file_name, bytes).unwrap();
+
+        let region = RegionFile::new(&file_name).unwrap();
+        assert_eq!(region.last_modified().unwrap(), 300);
+        assert_eq!(region.changed_since(0).unwrap(), [5, 7, 1023]);
+        assert_eq!(region.changed_since(100).unwrap(), [7, 1023]);
+        assert_eq!(region.changed_since(250).unwrap(), [1023]);
+        assert!(region.changed_since(300).unwrap().is_empty());
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    // A new region in an empty folder, named so that its chunks can be saved in external files
+    fn new_region(test: &str) -> (PathBuf, RegionFile) {
+        let folder =
+            std::env::temp_dir().join(format!("mine_viewer_{}_{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&folder);
+        std::fs::create_dir_all(&folder).unwrap();
+        let region = RegionFile::open_writable(&folder.join("r.-1.2.mca")).unwrap();
+        (folder, region)
+    }
+
+    fn read(region: &RegionFile, index: usize) -> Vec<u8> {
+        let (offset, size) = region.read_header().unwrap()[index];
+        region.read_chunk(index, offset, size).unwrap()
+    }
+
+    #[test]
+    fn write_and_read_chunks() {
+        let (folder, mut region) = new_region("write");
+        // Bytes which can't be compressed, so that the chunk doesn't fit in the region
+        let big: Vec<u8> = (0..1_200_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect();
+        let small = b"small chunk".to_vec();
+
+        region.write_chunk(0, &small, Compression::Zlib).unwrap();
+        region.write_chunk(1, &small, Compression::Gzip).unwrap();
+        region.write_chunk(33, &big, Compression::None).unwrap();
+        region
+            .write_chunk(1023, &big[..9000], Compression::None)
+            .unwrap();
+
+        let region = RegionFile::new(&folder.join("r.-1.2.mca")).unwrap();
+        assert_eq!(read(&region, 0), small);
+        assert_eq!(read(&region, 1), small);
+        assert_eq!(read(&region, 33), big);
+        assert_eq!(read(&region, 1023), &big[..9000]);
+        assert_eq!(region.read_header().unwrap()[2], (0, 0));
+        // Chunk 33 is at x 1, z 1 of the region -1 2
+        assert!(folder.join("c.-31.65.mcc").exists());
+        assert!(region.read_timestamps().unwrap()[33] > 0);
+        let mapped = RegionFile::new_mapped(&folder.join("r.-1.2.mca")).unwrap();
+        assert_eq!(read(&mapped, 1), small);
+        assert_eq!(read(&mapped, 33), big);
+        drop(mapped);
+
+        // Rewriting the chunk small enough removes its external file
+        let mut region = RegionFile::open_writable(&folder.join("r.-1.2.mca")).unwrap();
+        region.write_chunk(33, &small, Compression::Zlib).unwrap();
+        assert!(!folder.join("c.-31.65.mcc").exists());
+
+        region.delete_chunk(0).unwrap();
+        region.compact().unwrap();
+        assert_eq!(region.read_header().unwrap()[0], (0, 0));
+        assert_eq!(read(&region, 1), small);
+        assert_eq!(read(&region, 33), small);
+        assert_eq!(read(&region, 1023), &big[..9000]);
+        // Two sectors for the tables, one for each small chunk and three for the last one
+        assert_eq!(region.len(), 7 * SECTOR_SIZE);
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn compact_overlapping_chunks() {
+        let (folder, mut region) = new_region("overlap");
+        let long: Vec<u8> = (0..6000).map(|i| i as u8).collect();
+        region.write_chunk(0, &long, Compression::None).unwrap();
+        region.write_chunk(1, b"after", Compression::None).unwrap();
+        let (offset, _) = region.read_header().unwrap()[0];
+        assert_eq!(region.read_header().unwrap()[1], (offset + 2, 1));
+        // A broken entry using the end of chunk 0 and all of chunk 1
+        region.write_location(2, offset + 1, 2).unwrap();
+
+        region.compact().unwrap();
+        assert_eq!(read(&region, 0), long);
+        assert_eq!(read(&region, 1), b"after");
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn empty_palette() {
+        let mut comp = Compound::new();
+        comp.insert("Y".to_owned(), Tag::Byte(2));
+        comp.insert("Palette".to_owned(), Tag::List(vec![]));
+        comp.insert("BlockStates".to_owned(), Tag::LongArray(vec![0; 256]));
+        let bytes = to_nbt(comp);
+        let root = NBTParser::new(&bytes).read_compound().unwrap();
+        let section: SectionNbt = nbt::de::from_tag_ref(&root.as_compound().unwrap()[""]).unwrap();
+
+        let (section, y) = ChunkSection::new(&section, VERSION_1_16, &GraphPropsMap::new());
+        assert_eq!(y, 2);
+        assert!(section.names.is_empty());
+        assert!(section.indexes.is_empty());
+    }
+
+    #[test]
+    fn property_order() {
+        let block = |props: &[(&str, &str)]| {
+            let mut properties = Compound::new();
+            for (key, value) in props {
+                properties.insert(key.to_string(), Tag::String(value.to_string()));
+            }
+            let mut block = Compound::new();
+            block.insert(
+                "Name".to_owned(),
+                Tag::String("minecraft:oak_stairs".to_owned()),
+            );
+            block.insert("Properties".to_owned(), Tag::Compound(properties));
+            Tag::Compound(block)
+        };
+        let mut comp = Compound::new();
+        comp.insert("Y".to_owned(), Tag::Byte(0));
+        comp.insert(
+            "Palette".to_owned(),
+            Tag::List(vec![
+                block(&[
+                    ("waterlogged", "false"),
+                    ("facing", "east"),
+                    ("half", "top"),
+                ]),
+                block(&[
+                    ("half", "bottom"),
+                    ("facing", "west"),
+                    ("waterlogged", "true"),
+                ]),
+            ]),
+        );
+        let bytes = to_nbt(comp);
+        let root = NBTParser::new(&bytes).read_compound().unwrap();
+        let section: SectionNbt = nbt::de::from_tag_ref(&root.as_compound().unwrap()[""]).unwrap();
+
+        // The properties are kept in the order of the file
+        // While the graphic ones follow the order of the blockstates file
+        let mut graphic_set = GraphPropsMap::new();
+        let graphics = [("facing", 0), ("half", 1)];
+        let graphics = graphics.iter().map(|(key, i)| (key.to_string(), *i));
+        graphic_set.insert("minecraft:oak_stairs".to_owned(), graphics.collect());
+        let (section, _) = ChunkSection::new(&section, VERSION_1_16, &graphic_set);
+        assert_eq!(
+            section.properties,
+            [
+                "waterlogged=false,facing=east,half=top",
+                "half=bottom,facing=west,waterlogged=true"
+            ]
+        );
+        assert_eq!(
+            section.graphic_props,
+            ["facing=east,half=top", "facing=west,half=bottom"]
+        );
+    }
+
+    // A 1.18 chunk of a world with the given sections, and the light only ones around it
+    fn chunk_nbt(min_section: i32, sections: i32, heights: &[usize], bits: usize) -> Vec<u8> {
+        let mut sections_nbt = Vec::new();
+        for y in min_section - 1..=min_section + sections {
+            let mut section = Compound::new();
+            section.insert("Y".to_owned(), Tag::Byte(y as i8));
+            if y >= min_section && y < min_section + sections {
+                let mut block = Compound::new();
+                block.insert("Name".to_owned(), Tag::String("minecraft:air".to_owned()));
+                let mut states = Compound::new();
+                states.insert("palette".to_owned(), Tag::List(vec![Tag::Compound(block)]));
+                section.insert("block_states".to_owned(), Tag::Compound(states));
+            }
+            sections_nbt.push(Tag::Compound(section));
+        }
+        let mut heightmaps = Compound::new();
+        heightmaps.insert(
+            "WORLD_SURFACE".to_owned(),
+            Tag::LongArray(pack(heights, bits, true)),
+        );
+
+        let mut comp = Compound::new();
+        comp.insert("DataVersion".to_owned(), Tag::Int(VERSION_1_18));
+        comp.insert("yPos".to_owned(), Tag::Int(min_section));
+        comp.insert("sections".to_owned(), Tag::List(sections_nbt));
+        comp.insert("Heightmaps".to_owned(), Tag::Compound(heightmaps));
+        to_nbt(comp)
+    }
+
+    #[test]
+    fn heightmap_bits() {
+        // 11 and 12 bits heights are saved in the same number of longs
+        for (sections, bits) in [(24, 9), (127, 11), (254, 12)] {
+            let heights: Vec<usize> = (0..256).map(|i| i * 7 % (sections * 16 + 1)).collect();
+            let bytes = chunk_nbt(-4, sections as i32, &heights, bits);
+            let root = NBTParser::new(&bytes).read_compound().unwrap();
+            let chunk: ChunkNbt = nbt::de::from_tag_ref(&root.as_compound().unwrap()[""]).unwrap();
+
+            let expected: Vec<i32> = heights.iter().map(|height| *height as i32 - 64).collect();
+            let unpacked = chunk.heightmap(Heightmap::WorldSurface);
+            assert_eq!(unpacked, Some(expected), "{} sections", sections);
+            assert_eq!(chunk.heightmap(Heightmap::OceanFloor), None);
+        }
+
+        // Heights with the bits of another world height are skipped
+        let bytes = chunk_nbt(-4, 127, &[0; 256], 9);
+        let root = NBTParser::new(&bytes).read_compound().unwrap();
+        let chunk: ChunkNbt = nbt::de::from_tag_ref(&root.as_compound().unwrap()[""]).unwrap();
+        assert_eq!(chunk.heightmap(Heightmap::WorldSurface), None);
+    }
+
+    #[test]
+    fn legacy_heightmap() {
+        let heights: Vec<i32> = (0..256).map(|i| i % 200).collect();
+        let mut level = Compound::new();
+        level.insert("Sections".to_owned(), Tag::List(vec![]));
+        level.insert("HeightMap".to_owned(), Tag::IntArray(heights.clone()));
+        let mut comp = Compound::new();
+        comp.insert("DataVersion".to_owned(), Tag::Int(1343));
+        comp.insert("Level".to_owned(), Tag::Compound(level));
+        let bytes = to_nbt(comp);
+        let root = NBTParser::new(&bytes).read_compound().unwrap();
+        let chunk: ChunkNbt = nbt::de::from_tag_ref(&root.as_compound().unwrap()[""]).unwrap();
+
+        assert_eq!(chunk.heightmap(Heightmap::MotionBlocking), Some(heights));
+        assert_eq!(chunk.heightmap(Heightmap::WorldSurface), None);
+    }
+
+    // The graphic properties of blocks without any
+    fn graphic_set(names: &[&str]) -> GraphPropsMap {
+        names
+            .iter()
+            .map(|name| (format!("minecraft:{}", name), HashMap::new()))
+            .collect()
+    }
+
+    // A 1.18+ section, with only light if there is no palette
+    fn section_1_18(y: i8, palette: &[&str], states: Option<Vec<i64>>) -> Tag {
+        let mut section = Compound::new();
+        section.insert("Y".to_owned(), Tag::Byte(y));
+        if !palette.is_empty() {
+            let palette = palette.iter().map(|name| {
+                let mut block = Compound::new();
+                block.insert(
+                    "Name".to_owned(),
+                    Tag::String(format!("minecraft:{}", name)),
+                );
+                Tag::Compound(block)
+            });
+            let mut block_states = Compound::new();
+            block_states.insert("palette".to_owned(), Tag::List(palette.collect()));
+            if let Some(states) = states {
+                block_states.insert("data".to_owned(), Tag::LongArray(states));
+            }
+            section.insert("block_states".to_owned(), Tag::Compound(block_states));
+        }
+        section.insert("SkyLight".to_owned(), Tag::ByteArray(vec![0; 2048]));
+        Tag::Compound(section)
+    }
+
+    #[test]
+    fn chunk_1_18() {
+        let indexes: Vec<usize> = (0..4096).map(|i| i % 3).collect();
+        let sections = vec![
+            section_1_18(-5, &[], None),
+            section_1_18(-4, &["deepslate"], None),
+            section_1_18(0, &["air", "stone", "dirt"], Some(pack(&indexes, 4, true))),
+            section_1_18(1, &["air"], None),
+            section_1_18(20, &["glass"], None),
+            section_1_18(21, &[], None),
+        ];
+        let mut comp = Compound::new();
+        comp.insert("DataVersion".to_owned(), Tag::Int(2860));
+        comp.insert("yPos".to_owned(), Tag::Int(-4));
+        comp.insert("sections".to_owned(), Tag::List(sections));
+        comp.insert("Status".to_owned(), Tag::String("full".to_owned()));
+        let bytes = to_nbt(comp);
+
+        let graphic_set = graphic_set(&["air", "deepslate", "stone", "dirt", "glass"]);
+        let filter = nbt::Filter::new(CHUNK_PATHS);
+        let format = RegionFormat::Anvil;
+        let heightmap = Heightmap::WorldSurface;
+        let chunk = parse_chunk(&bytes, &filter, format, heightmap, &graphic_set).unwrap();
+
+        assert_eq!(chunk.height_range(), (-64, 336));
+        // The section without block data is filled with its only block
+        assert_eq!(chunk.get_block(0, -64, 0), "minecraft:deepslate");
+        assert_eq!(chunk.get_block(15, -49, 15), "minecraft:deepslate");
+        assert_eq!(chunk.get_block(0, -48, 0), EMPTY_BLOCK);
+        assert_eq!(chunk.get_block(0, -65, 0), EMPTY_BLOCK);
+        for (x, y, z) in [(0, 0, 0), (1, 0, 0), (5, 3, 9), (15, 15, 15)] {
+            let name =
+                ["minecraft:air", "minecraft:stone", "minecraft:dirt"][(y * 256 + z * 16 + x) % 3];
+            assert_eq!(chunk.get_block(x, y as i32, z), name, "{} {} {}", x, y, z);
+        }
+        assert_eq!(chunk.get_block(7, 320, 7), "minecraft:glass");
+        assert_eq!(chunk.get_block(7, 335, 7), "minecraft:glass");
+        assert_eq!(chunk.get_block(7, 336, 7), EMPTY_BLOCK);
+    }
+
+    #[test]
+    fn mcregion_chunk() {
+        // The blocks are ordered by x, then z, then y, on 128 blocks high columns
+        let index = |x: usize, y: usize, z: usize| y + z * 128 + x * 128 * 16;
+        let mut blocks = vec![0; 16 * 16 * 128];
+        let mut data = vec![0; 16 * 16 * 128 / 2];
+        let mut set = |(x, y, z), id: i8, meta: i8| {
+            let i = index(x, y, z);
+            blocks[i] = id;
+            // Two blocks share each byte of data, the first one uses the low bits
+            data[i / 2] |= meta << ((i % 2) * 4);
+        };
+        set((0, 0, 0), 7, 0);
+        set((3, 70, 5), 35, 14);
+        set((3, 71, 5), 35, 1);
+        set((15, 127, 15), 1, 0);
+        let mut level = Compound::new();
+        level.insert("Blocks".to_owned(), Tag::ByteArray(blocks));
+        level.insert("Data".to_owned(), Tag::ByteArray(data));
+        level.insert("xPos".to_owned(), Tag::Int(0));
+        let mut comp = Compound::new();
+        comp.insert("Level".to_owned(), Tag::Compound(level));
+        let bytes = to_nbt(comp);
+
+        let graphic_set = graphic_set(&["air", "bedrock", "red_wool", "orange_wool", "stone"]);
+        let filter = nbt::Filter::new(MCREGION_PATHS);
+        let format = RegionFormat::McRegion;
+        let heightmap = Heightmap::WorldSurface;
+        let chunk = parse_chunk(&bytes, &filter, format, heightmap, &graphic_set).unwrap();
+
+        assert_eq!(chunk.height_range(), (0, 128));
+        assert_eq!(chunk.get_block(0, 0, 0), "minecraft:bedrock");
+        assert_eq!(chunk.get_block(3, 70, 5), "minecraft:red_wool");
+        assert_eq!(chunk.get_block(3, 71, 5), "minecraft:orange_wool");
+        assert_eq!(chunk.get_block(5, 70, 3), EMPTY_BLOCK);
+        assert_eq!(chunk.get_block(15, 127, 15), "minecraft:stone");
+        assert_eq!(chunk.get_block(15, 128, 15), EMPTY_BLOCK);
+    }
+
+    #[test]
+    fn region_names() {
+        let format = |name: &str| RegionFormat::from_path(Path::new(name));
+        let position = |name: &str| parse_region_name(Path::new(name));
+        assert_eq!(
+            format("world/region/r.-1.2.mcr"),
+            Some(RegionFormat::McRegion)
+        );
+        assert_eq!(position("world/region/r.-1.2.mcr"), Some((-1, 2)));
+        assert_eq!(format("r.0.-3.mca"), Some(RegionFormat::Anvil));
+        assert_eq!(position("r.0.-3.mca"), Some((0, -3)));
+
+        // External chunks, temporary files and backups are not regions
+        for name in ["c.1.2.mcc", "r.0.0.mca.tmp", "r.0.0.mca.bak", "r.0.0.mca~"] {
+            assert_eq!(format(name), None, "{}", name);
+        }
+        assert_eq!(position("c.1.2.mcc"), None);
+        assert_eq!(position("r.0.0.mca.tmp"), None);
+        assert_eq!(position("r.x.0.mca"), None);
+    }
 }
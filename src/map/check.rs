@@ -0,0 +1,223 @@
+// Finds broken chunks in region files, and optionally removes them
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::{sectors_for, RegionFile, HEADER_SECTORS, REGION_SIZE};
+use crate::nbt;
+
+#[derive(Debug)]
+pub enum Problem {
+    // The sectors of the chunk are in the header or after the end of the file
+    OutsideFile,
+    // The chunk uses some of the sectors of another chunk (its index)
+    Overlap(usize),
+    // The length saved at the start of the chunk doesn't fit in its sectors
+    BadLength(u32),
+    // The chunk couldn't be read or decompressed
+    BadData(io::Error),
+    // The decompressed chunk isn't valid nbt
+    BadNbt(nbt::Error),
+}
+
+impl Problem {
+    // Chunks with this problem are removed when repairing
+    // Overlapping chunks are moved instead, if they can be read
+    fn is_broken(&self) -> bool {
+        !matches!(self, Problem::Overlap(_))
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::OutsideFile => write!(f, "sectors outside of the file"),
+            Problem::Overlap(index) => write!(f, "sectors overlap with chunk {}", index),
+            Problem::BadLength(length) => {
+                write!(f, "length {} doesn't fit in the sectors", length)
+            }
+            Problem::BadData(err) => write!(f, "can't read the chunk: {}", err),
+            Problem::BadNbt(err) => write!(f, "invalid nbt: {}", err),
+        }
+    }
+}
+
+// The problems of each chunk, with the index of the chunk in the header
+pub struct Report {
+    pub problems: Vec<(usize, Problem)>,
+}
+
+impl Report {
+    pub fn print(&self, file_name: &Path) {
+        for (index, problem) in &self.problems {
+            println!(
+                "{}: chunk {} ({}, {}): {}",
+                file_name.display(),
+                index,
+                index % REGION_SIZE,
+                index / REGION_SIZE,
+                problem
+            );
+        }
+    }
+}
+
+pub fn check_region(file_name: &Path) -> io::Result<Report> {
+    let region = RegionFile::new(file_name)?;
+    let header = region.read_header()?;
+    let file_sectors = sectors_for(region.len() as usize);
+
+    let mut problems = Vec::new();
+    for (index, &(offset, size)) in header.iter().enumerate() {
+        if offset == 0 && size == 0 {
+            continue;
+        }
+        let (start, end) = (u64::from(offset), u64::from(offset) + u64::from(size));
+        if start < HEADER_SECTORS || size == 0 || end > file_sectors {
+            problems.push((index, Problem::OutsideFile));
+            continue;
+        }
+
+        // Report each overlap once, on the chunk which comes later in the header
+        let overlap = header[..index]
+            .iter()
+            .position(|&(other_offset, other_size)| {
+                let other_start = u64::from(other_offset);
+                other_size != 0 && start < other_start + u64::from(other_size) && other_start < end
+            });
+        if let Some(other) = overlap {
+            problems.push((index, Problem::Overlap(other)));
+        }
+
+        // The chunk must fit in its sectors, 4 bytes for the length must be added
+        let length = match region.read_chunk_header(offset) {
+            Ok((length, _)) => length,
+            Err(err) => {
+                problems.push((index, Problem::BadData(err)));
+                continue;
+            }
+        };
+        if length == 0 || sectors_for(length as usize + 4) > u64::from(size) {
+            problems.push((index, Problem::BadLength(length)));
+            continue;
+        }
+
+        match region.read_chunk(index, offset, size) {
+            Ok(chunk) => {
+                if let Err(err) = nbt::NBTParser::new(&chunk).read_compound() {
+                    problems.push((index, Problem::BadNbt(err)));
+                }
+            }
+            Err(err) => problems.push((index, Problem::BadData(err))),
+        }
+    }
+
+    Ok(Report { problems })
+}
+
+// Removes the broken chunks, moves the overlapping ones to their own sectors
+// And then compacts the region
+// The region is first copied to a .bak file, which is put back if the repair fails
+// Returns the path of the copy
+pub fn repair_region(file_name: &Path, report: &Report) -> io::Result<PathBuf> {
+    let mut backup_name = file_name.as_os_str().to_owned();
+    backup_name.push(".bak");
+    let backup_name = PathBuf::from(backup_name);
+    fs::copy(file_name, &backup_name)?;
+
+    match repair_in_place(file_name, report) {
+        Ok(()) => Ok(backup_name),
+        Err(err) => {
+            fs::rename(&backup_name, file_name)?;
+            Err(err)
+        }
+    }
+}
+
+fn repair_in_place(file_name: &Path, report: &Report) -> io::Result<()> {
+    let mut region = RegionFile::open_writable(file_name)?;
+
+    let is_broken = |index: usize| {
+        report
+            .problems
+            .iter()
+            .any(|(i, problem)| *i == index && problem.is_broken())
+    };
+    for (index, _) in &report.problems {
+        if is_broken(*index) {
+            region.delete_chunk(*index)?;
+        }
+    }
+
+    for (index, problem) in &report.problems {
+        if let Problem::Overlap(_) = problem {
+            if is_broken(*index) {
+                continue;
+            }
+            // Rewriting the chunk gives it sectors which aren't used by other chunks
+            let (offset, size) = region.read_header()?[*index];
+            let chunk = region.read_chunk(*index, offset, size)?;
+            let timestamp = region.read_timestamps()?[*index];
+            region.write_chunk(*index, &chunk, nbt::writer::Compression::Zlib)?;
+            region.write_timestamp(*index, timestamp)?;
+        }
+    }
+
+    region.compact()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nbt::writer::{self, Compression};
+    use std::io::Write;
+
+    #[test]
+    fn check_and_repair() {
+        let folder = std::env::temp_dir().join(format!("mine_viewer_check_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        let file_name = folder.join("r.0.0.mca");
+
+        let chunk = writer::to_bytes("", &nbt::Compound::new(), Compression::None).unwrap();
+        let mut region = RegionFile::open_writable(&file_name).unwrap();
+        region.write_chunk(0, &chunk, Compression::Zlib).unwrap();
+        region
+            .write_chunk(1, &[99, 1, 2], Compression::Zlib)
+            .unwrap();
+        // A chunk in the last sector, which is cut after 2 bytes
+        region.write_location(2, 4, 1).unwrap();
+        drop(region);
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&file_name)
+            .unwrap()
+            .write_all(&[0, 0])
+            .unwrap();
+
+        let report = check_region(&file_name).unwrap();
+        let problems: Vec<(usize, String)> = report
+            .problems
+            .iter()
+            .map(|(index, problem)| (*index, format!("{:?}", problem)))
+            .collect();
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].0 == 1 && problems[0].1.starts_with("BadNbt"));
+        assert!(problems[1].0 == 2 && problems[1].1.starts_with("BadData"));
+
+        let broken = fs::read(&file_name).unwrap();
+        let backup = repair_region(&file_name, &report).unwrap();
+        assert_eq!(fs::read(&backup).unwrap(), broken);
+        assert!(check_region(&file_name).unwrap().problems.is_empty());
+        let region = RegionFile::new(&file_name).unwrap();
+        let header = region.read_header().unwrap();
+        assert_eq!((header[1], header[2]), ((0, 0), (0, 0)));
+        assert_eq!(
+            region.read_chunk(0, header[0].0, header[0].1).unwrap(),
+            chunk
+        );
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
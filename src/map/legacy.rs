@@ -0,0 +1,618 @@
+// Worlds before 1.13 save each block as a numeric id and 4 bits of metadata
+// The ids are converted to the block states used from 1.13 (the "flattening")
+// So that old sections can be rendered with the same palette as new ones
+
+use std::collections::HashMap;
+use std::fmt::Display;
+
+// The order of the colors in the metadata of wool, glass, clay, ...
+const COLORS: [&str; 16] = [
+    "white",
+    "orange",
+    "magenta",
+    "light_blue",
+    "yellow",
+    "lime",
+    "pink",
+    "gray",
+    "light_gray",
+    "cyan",
+    "purple",
+    "blue",
+    "brown",
+    "green",
+    "red",
+    "black",
+];
+
+const WOODS: [&str; 6] = ["oak", "spruce", "birch", "jungle", "acacia", "dark_oak"];
+
+// The kinds of stone slabs, the ones of id 43 and 44
+const SLABS: [&str; 8] = [
+    "stone",
+    "sandstone",
+    "petrified_oak",
+    "cobblestone",
+    "brick",
+    "stone_brick",
+    "nether_brick",
+    "quartz",
+];
+
+const FLOWERS: [&str; 9] = [
+    "poppy",
+    "blue_orchid",
+    "allium",
+    "azure_bluet",
+    "red_tulip",
+    "orange_tulip",
+    "white_tulip",
+    "pink_tulip",
+    "oxeye_daisy",
+];
+
+const DOUBLE_PLANTS: [&str; 6] = [
+    "sunflower",
+    "lilac",
+    "tall_grass",
+    "large_fern",
+    "rose_bush",
+    "peony",
+];
+
+// Facings used by blocks which can point in every direction (pistons, dispensers, ...)
+const FACING: [&str; 6] = ["down", "up", "north", "south", "west", "east"];
+// Facings used by pumpkins, beds, glazed terracotta, ...
+const HORIZONTAL: [&str; 4] = ["south", "west", "north", "east"];
+// Facings used by stairs
+const STAIRS: [&str; 4] = ["east", "west", "south", "north"];
+
+const RAILS: [&str; 10] = [
+    "north_south",
+    "east_west",
+    "ascending_east",
+    "ascending_west",
+    "ascending_north",
+    "ascending_south",
+    "south_east",
+    "south_west",
+    "north_west",
+    "north_east",
+];
+
+// The name of a block and its properties, in the order they are saved in
+pub type State = (String, Vec<(&'static str, String)>);
+
+// Ex: ("oak_log", [("axis", "y")]) -> ("minecraft:oak_log", [("axis", "y")])
+fn state<const N: usize>(name: &str, props: [(&'static str, &dyn Display); N]) -> State {
+    let props = props
+        .iter()
+        .map(|(key, value)| (*key, value.to_string()))
+        .collect();
+    (format!("minecraft:{}", name), props)
+}
+
+fn block(name: &str) -> State {
+    state(name, [])
+}
+
+// Chests, ladders, wall signs, ... use the same numbers as `FACING`
+// But can't point up or down
+fn wall_facing(meta: u8) -> &'static str {
+    match meta {
+        3 => "south",
+        4 => "west",
+        5 => "east",
+        _ => "north",
+    }
+}
+
+// Logs, hay and bone blocks save their axis in the upper two bits
+fn axis(meta: u8) -> &'static str {
+    match meta >> 2 {
+        1 => "x",
+        2 => "z",
+        _ => "y",
+    }
+}
+
+fn half(meta: u8, upper: &'static str, lower: &'static str) -> &'static str {
+    if meta & 8 != 0 {
+        upper
+    } else {
+        lower
+    }
+}
+
+fn stairs(name: &str, meta: u8) -> State {
+    let half = if meta & 4 != 0 { "top" } else { "bottom" };
+    state(
+        name,
+        [("facing", &STAIRS[usize::from(meta & 3)]), ("half", &half)],
+    )
+}
+
+fn slab(name: &str, meta: u8) -> State {
+    state(name, [("type", &half(meta, "top", "bottom"))])
+}
+
+fn double_slab(name: &str) -> State {
+    state(name, [("type", &"double")])
+}
+
+fn door(name: &str, meta: u8) -> State {
+    // The upper half only knows the hinge side, the facing is saved in the lower one
+    if meta & 8 != 0 {
+        state(name, [("half", &"upper")])
+    } else {
+        let facing = ["east", "south", "west", "north"][usize::from(meta & 3)];
+        state(name, [("facing", &facing), ("half", &"lower")])
+    }
+}
+
+fn trapdoor(name: &str, meta: u8) -> State {
+    let facing = ["north", "south", "west", "east"][usize::from(meta & 3)];
+    let half = if meta & 8 != 0 { "top" } else { "bottom" };
+    let open = meta & 4 != 0;
+    state(
+        name,
+        [("facing", &facing), ("half", &half), ("open", &open)],
+    )
+}
+
+fn rail(name: &str, meta: u8) -> State {
+    // Powered, detector and activator rails can't turn
+    let shape = RAILS[usize::from(meta & 7).min(5)];
+    state(name, [("shape", &shape)])
+}
+
+fn with_facing(name: &str, facing: &str) -> State {
+    state(name, [("facing", &facing)])
+}
+
+fn with_axis(name: &str, meta: u8) -> State {
+    state(name, [("axis", &axis(meta))])
+}
+
+fn with_age(name: &str, age: u8) -> State {
+    state(name, [("age", &age)])
+}
+
+fn leaves(wood: &str) -> State {
+    state(
+        &format!("{}_leaves", wood),
+        [("distance", &7), ("persistent", &false)],
+    )
+}
+
+fn log(wood: &str, meta: u8) -> State {
+    // Logs with bark on all the sides
+    if meta >> 2 == 3 {
+        state(&format!("{}_wood", wood), [("axis", &"y")])
+    } else {
+        with_axis(&format!("{}_log", wood), meta)
+    }
+}
+
+fn torch(name: &str, wall_name: &str, meta: u8) -> State {
+    match meta {
+        1 => with_facing(wall_name, "east"),
+        2 => with_facing(wall_name, "west"),
+        3 => with_facing(wall_name, "south"),
+        4 => with_facing(wall_name, "north"),
+        _ => block(name),
+    }
+}
+
+// The 1.13 block state of a numeric id and its metadata
+// Unknown ids are turned into air, unknown metadata into the default variant of the block
+pub fn block_state(id: u16, meta: u8) -> State {
+    let m = usize::from(meta);
+    let wood = WOODS[(m & 7).min(5)];
+    let color = COLORS[m];
+    match id {
+        1 => block(
+            [
+                "stone",
+                "granite",
+                "polished_granite",
+                "diorite",
+                "polished_diorite",
+                "andesite",
+                "polished_andesite",
+            ][m.min(6)],
+        ),
+        2 => state("grass_block", [("snowy", &false)]),
+        3 => match meta {
+            1 => block("coarse_dirt"),
+            2 => state("podzol", [("snowy", &false)]),
+            _ => block("dirt"),
+        },
+        4 => block("cobblestone"),
+        5 => block(&format!("{}_planks", wood)),
+        6 => state(&format!("{}_sapling", wood), [("stage", &0)]),
+        7 => block("bedrock"),
+        8 | 9 => state("water", [("level", &meta)]),
+        10 | 11 => state("lava", [("level", &meta)]),
+        12 => block(if meta == 1 { "red_sand" } else { "sand" }),
+        13 => block("gravel"),
+        14 => block("gold_ore"),
+        15 => block("iron_ore"),
+        16 => block("coal_ore"),
+        17 => log(WOODS[m & 3], meta),
+        18 => leaves(WOODS[m & 3]),
+        19 => block(if meta == 1 { "wet_sponge" } else { "sponge" }),
+        20 => block("glass"),
+        21 => block("lapis_ore"),
+        22 => block("lapis_block"),
+        23 => with_facing("dispenser", FACING[(m & 7).min(5)]),
+        24 => block(["sandstone", "chiseled_sandstone", "cut_sandstone"][m.min(2)]),
+        25 => block("note_block"),
+        26 => state(
+            "red_bed",
+            [
+                ("facing", &HORIZONTAL[m & 3]),
+                ("part", &half(meta, "head", "foot")),
+            ],
+        ),
+        27 => rail("powered_rail", meta),
+        28 => rail("detector_rail", meta),
+        29 => with_facing("sticky_piston", FACING[(m & 7).min(5)]),
+        30 => block("cobweb"),
+        31 => block(["dead_bush", "grass", "fern"][m.min(2)]),
+        32 => block("dead_bush"),
+        33 => with_facing("piston", FACING[(m & 7).min(5)]),
+        34 => state(
+            "piston_head",
+            [
+                ("facing", &FACING[(m & 7).min(5)]),
+                ("type", &half(meta, "sticky", "normal")),
+            ],
+        ),
+        35 => block(&format!("{}_wool", color)),
+        36 => block("moving_piston"),
+        37 => block("dandelion"),
+        38 => block(FLOWERS[m.min(8)]),
+        39 => block("brown_mushroom"),
+        40 => block("red_mushroom"),
+        41 => block("gold_block"),
+        42 => block("iron_block"),
+        43 => match meta {
+            8 => block("smooth_stone"),
+            9 => block("smooth_sandstone"),
+            15 => block("smooth_quartz"),
+            _ => double_slab(&format!("{}_slab", SLABS[m & 7])),
+        },
+        44 => slab(&format!("{}_slab", SLABS[m & 7]), meta),
+        45 => block("bricks"),
+        46 => block("tnt"),
+        47 => block("bookshelf"),
+        48 => block("mossy_cobblestone"),
+        49 => block("obsidian"),
+        50 => torch("torch", "wall_torch", meta),
+        51 => block("fire"),
+        52 => block("spawner"),
+        53 => stairs("oak_stairs", meta),
+        54 => with_facing("chest", wall_facing(meta)),
+        55 => block("redstone_wire"),
+        56 => block("diamond_ore"),
+        57 => block("diamond_block"),
+        58 => block("crafting_table"),
+        59 => with_age("wheat", meta & 7),
+        60 => state("farmland", [("moisture", &(meta & 7))]),
+        61 => state("furnace", [("facing", &wall_facing(meta)), ("lit", &false)]),
+        62 => state("furnace", [("facing", &wall_facing(meta)), ("lit", &true)]),
+        63 => state("sign", [("rotation", &meta)]),
+        64 => door("oak_door", meta),
+        65 => with_facing("ladder", wall_facing(meta)),
+        66 => state("rail", [("shape", &RAILS[m.min(9)])]),
+        67 => stairs("cobblestone_stairs", meta),
+        68 => with_facing("wall_sign", wall_facing(meta)),
+        69 => block("lever"),
+        70 => block("stone_pressure_plate"),
+        71 => door("iron_door", meta),
+        72 => block("oak_pressure_plate"),
+        73 => state("redstone_ore", [("lit", &false)]),
+        74 => state("redstone_ore", [("lit", &true)]),
+        75 | 76 => torch("redstone_torch", "redstone_wall_torch", meta),
+        77 => block("stone_button"),
+        78 => state("snow", [("layers", &((meta & 7) + 1))]),
+        79 => block("ice"),
+        80 => block("snow_block"),
+        81 => block("cactus"),
+        82 => block("clay"),
+        83 => block("sugar_cane"),
+        84 => block("jukebox"),
+        85 => block("oak_fence"),
+        86 => with_facing("carved_pumpkin", HORIZONTAL[m & 3]),
+        87 => block("netherrack"),
+        88 => block("soul_sand"),
+        89 => block("glowstone"),
+        90 => state(
+            "nether_portal",
+            [("axis", &if meta == 2 { "z" } else { "x" })],
+        ),
+        91 => with_facing("jack_o_lantern", HORIZONTAL[m & 3]),
+        92 => block("cake"),
+        93 | 94 => with_facing("repeater", HORIZONTAL[m & 3]),
+        95 => block(&format!("{}_stained_glass", color)),
+        96 => trapdoor("oak_trapdoor", meta),
+        97 => block(
+            [
+                "infested_stone",
+                "infested_cobblestone",
+                "infested_stone_bricks",
+                "infested_mossy_stone_bricks",
+                "infested_cracked_stone_bricks",
+                "infested_chiseled_stone_bricks",
+            ][m.min(5)],
+        ),
+        98 => block(
+            [
+                "stone_bricks",
+                "mossy_stone_bricks",
+                "cracked_stone_bricks",
+                "chiseled_stone_bricks",
+            ][m.min(3)],
+        ),
+        99 | 100 if meta == 10 || meta == 15 => block("mushroom_stem"),
+        99 => block("brown_mushroom_block"),
+        100 => block("red_mushroom_block"),
+        101 => block("iron_bars"),
+        102 => block("glass_pane"),
+        103 => block("melon"),
+        104 => with_age("pumpkin_stem", meta & 7),
+        105 => with_age("melon_stem", meta & 7),
+        106 => block("vine"),
+        107 => with_facing("oak_fence_gate", HORIZONTAL[m & 3]),
+        108 => stairs("brick_stairs", meta),
+        109 => stairs("stone_brick_stairs", meta),
+        110 => state("mycelium", [("snowy", &false)]),
+        111 => block("lily_pad"),
+        112 => block("nether_bricks"),
+        113 => block("nether_brick_fence"),
+        114 => stairs("nether_brick_stairs", meta),
+        115 => with_age("nether_wart", meta & 3),
+        116 => block("enchanting_table"),
+        117 => block("brewing_stand"),
+        118 => state("cauldron", [("level", &(meta & 3))]),
+        119 => block("end_portal"),
+        120 => state(
+            "end_portal_frame",
+            [("eye", &(meta & 4 != 0)), ("facing", &HORIZONTAL[m & 3])],
+        ),
+        121 => block("end_stone"),
+        122 => block("dragon_egg"),
+        123 => state("redstone_lamp", [("lit", &false)]),
+        124 => state("redstone_lamp", [("lit", &true)]),
+        125 => double_slab(&format!("{}_slab", wood)),
+        126 => slab(&format!("{}_slab", wood), meta),
+        127 => state(
+            "cocoa",
+            [
+                ("age", &(meta >> 2).min(2)),
+                ("facing", &["south", "west", "north", "east"][m & 3]),
+            ],
+        ),
+        128 => stairs("sandstone_stairs", meta),
+        129 => block("emerald_ore"),
+        130 => with_facing("ender_chest", wall_facing(meta)),
+        131 => block("tripwire_hook"),
+        132 => block("tripwire"),
+        133 => block("emerald_block"),
+        134 => stairs("spruce_stairs", meta),
+        135 => stairs("birch_stairs", meta),
+        136 => stairs("jungle_stairs", meta),
+        137 => block("command_block"),
+        138 => block("beacon"),
+        139 => block(if meta == 1 {
+            "mossy_cobblestone_wall"
+        } else {
+            "cobblestone_wall"
+        }),
+        140 => block("flower_pot"),
+        141 => with_age("carrots", meta & 7),
+        142 => with_age("potatoes", meta & 7),
+        143 => block("oak_button"),
+        // The kind of skull is saved in its block entity
+        144 => block("skeleton_skull"),
+        145 => with_facing(
+            ["anvil", "chipped_anvil", "damaged_anvil"][usize::from(meta >> 2).min(2)],
+            ["south", "west", "north", "east"][m & 3],
+        ),
+        146 => with_facing("trapped_chest", wall_facing(meta)),
+        147 => block("light_weighted_pressure_plate"),
+        148 => block("heavy_weighted_pressure_plate"),
+        149 | 150 => state(
+            "comparator",
+            [("facing", &HORIZONTAL[m & 3]), ("powered", &(id == 150))],
+        ),
+        151 => state("daylight_detector", [("inverted", &false)]),
+        152 => block("redstone_block"),
+        153 => block("nether_quartz_ore"),
+        154 => block("hopper"),
+        155 => match meta {
+            1 => block("chiseled_quartz_block"),
+            2 => state("quartz_pillar", [("axis", &"y")]),
+            3 => state("quartz_pillar", [("axis", &"x")]),
+            4 => state("quartz_pillar", [("axis", &"z")]),
+            _ => block("quartz_block"),
+        },
+        156 => stairs("quartz_stairs", meta),
+        157 => rail("activator_rail", meta),
+        158 => with_facing("dropper", FACING[(m & 7).min(5)]),
+        159 => block(&format!("{}_terracotta", color)),
+        160 => block(&format!("{}_stained_glass_pane", color)),
+        161 => leaves(WOODS[4 + (m & 1)]),
+        162 => log(WOODS[4 + (m & 1)], meta),
+        163 => stairs("acacia_stairs", meta),
+        164 => stairs("dark_oak_stairs", meta),
+        165 => block("slime_block"),
+        166 => block("barrier"),
+        167 => trapdoor("iron_trapdoor", meta),
+        168 => block(["prismarine", "prismarine_bricks", "dark_prismarine"][m.min(2)]),
+        169 => block("sea_lantern"),
+        170 => with_axis("hay_block", meta),
+        171 => block(&format!("{}_carpet", color)),
+        172 => block("terracotta"),
+        173 => block("coal_block"),
+        174 => block("packed_ice"),
+        175 => state(
+            DOUBLE_PLANTS[(m & 7).min(5)],
+            [("half", &half(meta, "upper", "lower"))],
+        ),
+        // The color of banners is saved in their block entity
+        176 => state("white_banner", [("rotation", &meta)]),
+        177 => with_facing("white_wall_banner", wall_facing(meta)),
+        178 => state("daylight_detector", [("inverted", &true)]),
+        179 => block(
+            [
+                "red_sandstone",
+                "chiseled_red_sandstone",
+                "cut_red_sandstone",
+            ][m.min(2)],
+        ),
+        180 => stairs("red_sandstone_stairs", meta),
+        181 if meta == 8 => block("smooth_red_sandstone"),
+        181 => double_slab("red_sandstone_slab"),
+        182 => slab("red_sandstone_slab", meta),
+        183 => with_facing("spruce_fence_gate", HORIZONTAL[m & 3]),
+        184 => with_facing("birch_fence_gate", HORIZONTAL[m & 3]),
+        185 => with_facing("jungle_fence_gate", HORIZONTAL[m & 3]),
+        186 => with_facing("dark_oak_fence_gate", HORIZONTAL[m & 3]),
+        187 => with_facing("acacia_fence_gate", HORIZONTAL[m & 3]),
+        188 => block("spruce_fence"),
+        189 => block("birch_fence"),
+        190 => block("jungle_fence"),
+        191 => block("dark_oak_fence"),
+        192 => block("acacia_fence"),
+        193 => door("spruce_door", meta),
+        194 => door("birch_door", meta),
+        195 => door("jungle_door", meta),
+        196 => door("acacia_door", meta),
+        197 => door("dark_oak_door", meta),
+        198 => with_facing("end_rod", FACING[(m & 7).min(5)]),
+        199 => block("chorus_plant"),
+        200 => with_age("chorus_flower", meta.min(5)),
+        201 => block("purpur_block"),
+        202 => with_axis("purpur_pillar", meta),
+        203 => stairs("purpur_stairs", meta),
+        204 => double_slab("purpur_slab"),
+        205 => slab("purpur_slab", meta),
+        206 => block("end_stone_bricks"),
+        207 => with_age("beetroots", meta & 3),
+        208 => block("grass_path"),
+        209 => block("end_gateway"),
+        210 => block("repeating_command_block"),
+        211 => block("chain_command_block"),
+        212 => with_age("frosted_ice", meta & 3),
+        213 => block("magma_block"),
+        214 => block("nether_wart_block"),
+        215 => block("red_nether_bricks"),
+        216 => with_axis("bone_block", meta),
+        217 => block("structure_void"),
+        218 => with_facing("observer", FACING[(m & 7).min(5)]),
+        219..=234 => with_facing(
+            &format!("{}_shulker_box", COLORS[usize::from(id - 219)]),
+            FACING[(m & 7).min(5)],
+        ),
+        235..=250 => with_facing(
+            &format!("{}_glazed_terracotta", COLORS[usize::from(id - 235)]),
+            HORIZONTAL[m & 3],
+        ),
+        251 => block(&format!("{}_concrete", color)),
+        252 => block(&format!("{}_concrete_powder", color)),
+        255 => block("structure_block"),
+        _ => (super::EMPTY_BLOCK.to_owned(), Vec::new()),
+    }
+}
+
+// Builds the palette of a section from the id and metadata of its 4096 blocks
+// `get` receives the index of a block, in the same order as `ChunkSection` indexes
+pub fn flatten<F>(get: F) -> (Vec<State>, Vec<usize>)
+where
+    F: Fn(usize) -> (u16, u8),
+{
+    let mut palette = Vec::new();
+    let mut known = HashMap::new();
+    let mut indexes = Vec::with_capacity(4096);
+
+    for i in 0..4096 {
+        let (id, mut meta) = get(i);
+        // The upper half of tall plants doesn't know which plant it is
+        // So it is taken from the lower half, if it is in the same section
+        if id == 175 && meta & 8 != 0 && i >= 256 {
+            let (below_id, below_meta) = get(i - 256);
+            if below_id == 175 && below_meta & 8 == 0 {
+                meta = 8 | below_meta;
+            }
+        }
+
+        let index = *known.entry((id, meta)).or_insert_with(|| {
+            palette.push(block_state(id, meta));
+            palette.len() - 1
+        });
+        indexes.push(index);
+    }
+
+    (palette, indexes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn properties() {
+        let (name, props) = block_state(17, 4 | 2);
+        assert_eq!(name, "minecraft:birch_log");
+        assert_eq!(props, vec![("axis", "x".to_owned())]);
+
+        let (name, props) = block_state(53, 4 | 1);
+        assert_eq!(name, "minecraft:oak_stairs");
+        assert_eq!(
+            props,
+            vec![("facing", "west".to_owned()), ("half", "top".to_owned())]
+        );
+
+        // Repeaters and comparators face the same way as pumpkins
+        let (name, props) = block_state(93, 4 | 2);
+        assert_eq!(name, "minecraft:repeater");
+        assert_eq!(props, vec![("facing", "north".to_owned())]);
+        let (name, props) = block_state(150, 1);
+        assert_eq!(name, "minecraft:comparator");
+        assert_eq!(
+            props,
+            vec![
+                ("facing", "west".to_owned()),
+                ("powered", "true".to_owned())
+            ]
+        );
+
+        assert_eq!(
+            block_state(4000, 0),
+            (super::super::EMPTY_BLOCK.to_owned(), vec![])
+        );
+    }
+
+    #[test]
+    fn flatten_tall_plants() {
+        // A rose bush in the two bottom layers, the rest is air
+        let (palette, indexes) = flatten(|i| match i / 256 {
+            0 => (175, 4),
+            1 => (175, 8),
+            _ => (0, 0),
+        });
+        let names: Vec<&str> = palette.iter().map(|(name, _)| &name[..]).collect();
+        assert_eq!(
+            names,
+            [
+                "minecraft:rose_bush",
+                "minecraft:rose_bush",
+                "minecraft:air"
+            ]
+        );
+        assert_eq!(palette[1].1, vec![("half", "upper".to_owned())]);
+        assert_eq!((indexes[0], indexes[256], indexes[512]), (0, 1, 2));
+    }
+}
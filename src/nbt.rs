@@ -1,22 +1,139 @@
+pub mod de;
+mod mutf8;
+pub mod path;
+// Nothing is serialized by the viewer yet, the serializer is checked by its tests
+#[cfg_attr(not(test), allow(dead_code))]
+pub mod ser;
+pub mod snbt;
+// Only region writing uses the writer for now, the rest is checked by its tests
+#[cfg_attr(not(test), allow(dead_code))]
+pub mod writer;
+
+use std::borrow::Cow;
+use std::fmt;
 use std::io;
-use std::io::prelude::*;
-use std::io::Cursor;
+use std::marker::PhantomData;
 
-use byteorder::{BigEndian, ReadBytesExt};
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use indexmap::IndexMap;
 use std::collections::HashMap;
 
-//pub type Result = std::result::Result<T, Error>;
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    // A tag id which is not between 1 and 12
+    UnknownTag(u8),
+    // The data ended in the middle of a tag
+    UnexpectedEof,
+    // A length which is negative or longer than the remaining data
+    InvalidLength(i32),
+    // A string or a name which could not be decoded
+    InvalidString,
+    // A varint longer than the number it should contain
+    InvalidVarint,
+    // Stringified nbt which could not be parsed
+    InvalidSnbt(&'static str),
+    // An nbt path which could not be parsed
+    InvalidPath(&'static str),
+    // The tags don't have the structure that was expected
+    Custom(String),
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub kind: ErrorKind,
+    // Position in bytes where the broken tag was found
+    // There is none for errors found in an already parsed tree
+    pub offset: Option<u64>,
+    // The path of the broken tag, ex: `Level.Sections[2].Palette`
+    pub path: String,
+}
+
+impl Error {
+    fn new(kind: ErrorKind, offset: u64) -> Error {
+        Error {
+            kind,
+            offset: Some(offset),
+            path: String::new(),
+        }
+    }
+
+    fn custom(message: String) -> Error {
+        Error {
+            kind: ErrorKind::Custom(message),
+            offset: None,
+            path: String::new(),
+        }
+    }
+
+    // The path is built while the error goes up the tag tree
+    // So that we don't have to keep track of it while parsing
+    fn in_key(mut self, key: &str) -> Error {
+        // The root compound usually has an empty name, which is left out
+        if key.is_empty() {
+            return self;
+        }
+        if !self.path.is_empty() && !self.path.starts_with('[') {
+            self.path.insert(0, '.');
+        }
+        self.path.insert_str(0, key);
+        self
+    }
+
+    fn in_index(mut self, index: i32) -> Error {
+        if !self.path.is_empty() && !self.path.starts_with('[') {
+            self.path.insert(0, '.');
+        }
+        self.path.insert_str(0, &format!("[{}]", index));
+        self
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::UnknownTag(id) => write!(f, "unknown tag type {}", id),
+            ErrorKind::UnexpectedEof => write!(f, "unexpected end of data"),
+            ErrorKind::InvalidLength(length) => write!(f, "invalid length {}", length),
+            ErrorKind::InvalidString => write!(f, "invalid string"),
+            ErrorKind::InvalidVarint => write!(f, "invalid varint"),
+            ErrorKind::InvalidSnbt(message) => write!(f, "invalid snbt, {}", message),
+            ErrorKind::InvalidPath(message) => write!(f, "invalid nbt path, {}", message),
+            ErrorKind::Custom(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if let Some(offset) = self.offset {
+            write!(f, " at byte {}", offset)?;
+        }
+        if !self.path.is_empty() {
+            write!(f, " in `{}`", self.path)?;
+        }
+        Ok(())
+    }
+}
 
-//pub enum Error {
+impl std::error::Error for Error {}
 
-//}
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
 
-// An NBT compound can be espress as an HashMap
-pub type Compound = HashMap<String, Tag>;
+// An NBT compound can be espress as a map
+// The tags keep the order they were inserted or parsed in
+// So that the same data is always printed and saved in the same way
+pub type Compound = IndexMap<String, Tag>;
 
 // There is no need to have an End tag
 // As the parsed structure doesn't use them
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Tag {
     Byte(i8),
     Short(i16),
@@ -33,6 +150,24 @@ pub enum Tag {
 }
 
 impl Tag {
+    // The id used to mark the type of this tag in a file
+    pub fn id(&self) -> u8 {
+        match self {
+            Tag::Byte(_) => 1,
+            Tag::Short(_) => 2,
+            Tag::Int(_) => 3,
+            Tag::Long(_) => 4,
+            Tag::Float(_) => 5,
+            Tag::Double(_) => 6,
+            Tag::ByteArray(_) => 7,
+            Tag::String(_) => 8,
+            Tag::List(_) => 9,
+            Tag::Compound(_) => 10,
+            Tag::IntArray(_) => 11,
+            Tag::LongArray(_) => 12,
+        }
+    }
+
     #[allow(dead_code)]
     pub fn as_compound(&self) -> Option<&Compound> {
         if let Tag::Compound(comp) = self {
@@ -78,6 +213,24 @@ impl Tag {
         }
     }
 
+    #[allow(dead_code)]
+    pub fn as_f32(&self) -> Option<&f32> {
+        if let Tag::Float(n) = self {
+            Some(n)
+        } else {
+            None
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn as_f64(&self) -> Option<&f64> {
+        if let Tag::Double(n) = self {
+            Some(n)
+        } else {
+            None
+        }
+    }
+
     #[allow(dead_code)]
     pub fn as_i8_vec(&self) -> Option<&Vec<i8>> {
         if let Tag::ByteArray(n) = self {
@@ -124,136 +277,914 @@ impl Tag {
     }
 }
 
-pub struct NBTParser {
-    bytes: Cursor<Vec<u8>>,
+// A tag borrowed from the parsed bytes, see `NBTParser`
+// It can be turned into an owned `Tag` with `Tag::from`
+#[derive(Debug, Clone, PartialEq)]
+pub enum TagRef<'a> {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(ArrayRef<'a, i8>),
+    String(Cow<'a, str>),
+    List(Vec<TagRef<'a>>),
+    Compound(CompoundRef<'a>),
+    IntArray(ArrayRef<'a, i32>),
+    LongArray(ArrayRef<'a, i64>),
+}
+
+pub type CompoundRef<'a> = IndexMap<Cow<'a, str>, TagRef<'a>>;
+
+impl<'a> TagRef<'a> {
+    #[allow(dead_code)]
+    pub fn as_compound(&self) -> Option<&CompoundRef<'a>> {
+        if let TagRef::Compound(comp) = self {
+            Some(comp)
+        } else {
+            None
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn as_i64_array(&self) -> Option<ArrayRef<'a, i64>> {
+        if let TagRef::LongArray(n) = self {
+            Some(*n)
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a> From<TagRef<'a>> for Tag {
+    fn from(tag: TagRef<'a>) -> Tag {
+        match tag {
+            TagRef::Byte(n) => Tag::Byte(n),
+            TagRef::Short(n) => Tag::Short(n),
+            TagRef::Int(n) => Tag::Int(n),
+            TagRef::Long(n) => Tag::Long(n),
+            TagRef::Float(n) => Tag::Float(n),
+            TagRef::Double(n) => Tag::Double(n),
+            TagRef::ByteArray(bytes) => Tag::ByteArray(bytes.to_vec()),
+            TagRef::String(string) => Tag::String(string.into_owned()),
+            TagRef::List(tags) => Tag::List(tags.into_iter().map(Tag::from).collect()),
+            TagRef::Compound(comp) => Tag::Compound(
+                comp.into_iter()
+                    .map(|(name, tag)| (name.into_owned(), Tag::from(tag)))
+                    .collect(),
+            ),
+            TagRef::IntArray(ints) => Tag::IntArray(ints.to_vec()),
+            TagRef::LongArray(longs) => Tag::LongArray(longs.to_vec()),
+        }
+    }
+}
+
+// The ways numbers can be saved in nbt data
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    // Java edition
+    BigEndian,
+    // Bedrock edition files, like level.dat
+    LittleEndian,
+    // Bedrock edition network data
+    // Like little endian, but ints and longs are zigzag varints
+    // And string lengths are unsigned varints
+    Network,
+}
+
+// What comes before the root compound
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Header {
+    // The root compound has a name, which is usually empty
+    Named,
+    // The root compound has no name, like in Java network data since 1.20.2
+    Nameless,
+    // Bedrock level.dat starts with two little endian i32:
+    // the version of the format and the length of the rest of the file
+    LevelDat,
+}
+
+// Reads an unsigned varint of at most `max_length` bytes
+// Returns the number and its length in bytes
+fn read_varint(bytes: &[u8], max_length: usize) -> std::result::Result<(u64, usize), ErrorKind> {
+    let mut number = 0;
+    for i in 0..max_length {
+        let byte = *bytes.get(i).ok_or(ErrorKind::UnexpectedEof)?;
+        number |= u64::from(byte & 0x7F) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok((number, i + 1));
+        }
+    }
+    Err(ErrorKind::InvalidVarint)
+}
+
+// Signed numbers are saved so that small negative numbers are short too
+// 0 -> 0, -1 -> 1, 1 -> 2, -2 -> 3, ...
+fn zigzag(n: u64) -> i64 {
+    (n >> 1) as i64 ^ -((n & 1) as i64)
+}
+
+// How many bytes can be used by the varint of a `size` bytes long number
+fn varint_length(size: usize) -> usize {
+    (size * 8).div_ceil(7)
+}
+
+// The types of the elements of the three array tags
+pub trait ArrayElement: Copy + 'static {
+    // The id of the array tag with this type of elements
+    const ID: u8;
+    // Size in bytes of each element
+    const SIZE: usize;
+    // Network nbt saves ints and longs as varints, but bytes as they are
+    const VARINT: bool;
+    // Reads an element from exactly `SIZE` bytes
+    fn read<E: ByteOrder>(bytes: &[u8]) -> Self;
+    fn from_varint(n: i64) -> Self;
+}
+
+impl ArrayElement for i8 {
+    const ID: u8 = 7;
+    const SIZE: usize = 1;
+    const VARINT: bool = false;
+    fn read<E: ByteOrder>(bytes: &[u8]) -> i8 {
+        bytes[0] as i8
+    }
+    fn from_varint(n: i64) -> i8 {
+        n as i8
+    }
+}
+
+impl ArrayElement for i32 {
+    const ID: u8 = 11;
+    const SIZE: usize = 4;
+    const VARINT: bool = true;
+    fn read<E: ByteOrder>(bytes: &[u8]) -> i32 {
+        E::read_i32(bytes)
+    }
+    fn from_varint(n: i64) -> i32 {
+        n as i32
+    }
 }
 
-impl NBTParser {
-    pub fn new(bytes: Vec<u8>) -> NBTParser {
+impl ArrayElement for i64 {
+    const ID: u8 = 12;
+    const SIZE: usize = 8;
+    const VARINT: bool = true;
+    fn read<E: ByteOrder>(bytes: &[u8]) -> i64 {
+        E::read_i64(bytes)
+    }
+    fn from_varint(n: i64) -> i64 {
+        n
+    }
+}
+
+// An array which is still in the parsed bytes
+// Its elements are decoded only when they are accessed
+#[derive(Clone, Copy, PartialEq)]
+pub struct ArrayRef<'a, T> {
+    bytes: &'a [u8],
+    len: usize,
+    encoding: Encoding,
+    element: PhantomData<T>,
+}
+
+#[allow(dead_code)]
+impl<'a, T: ArrayElement> ArrayRef<'a, T> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Panics if the index is out of bounds, like a slice
+    // Varints have to be read from the start of the array, so it is slower for network nbt
+    pub fn get(&self, index: usize) -> T {
+        if self.has_varints() {
+            return self.iter().nth(index).expect("array index out of bounds");
+        }
+        let bytes = &self.bytes[index * T::SIZE..(index + 1) * T::SIZE];
+        match self.encoding {
+            Encoding::BigEndian => T::read::<BigEndian>(bytes),
+            _ => T::read::<LittleEndian>(bytes),
+        }
+    }
+
+    pub fn iter(&self) -> ArrayIter<'a, T> {
+        ArrayIter { array: *self }
+    }
+
+    // The raw bytes of the array, saved with `encoding`
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    pub fn to_vec(self) -> Vec<T> {
+        self.iter().collect()
+    }
+
+    fn has_varints(&self) -> bool {
+        self.encoding == Encoding::Network && T::VARINT
+    }
+}
+
+impl<'a, T: ArrayElement + fmt::Debug> fmt::Debug for ArrayRef<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+// Decodes the elements of an array one at a time
+// The array shrinks from the front as the elements are read
+pub struct ArrayIter<'a, T> {
+    array: ArrayRef<'a, T>,
+}
+
+impl<'a, T: ArrayElement> Iterator for ArrayIter<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.array.is_empty() {
+            return None;
+        }
+
+        let (element, length) = if self.array.has_varints() {
+            // The varints were already checked by the parser
+            let (n, length) = read_varint(self.array.bytes, varint_length(T::SIZE))
+                .expect("invalid varint in a parsed array");
+            (T::from_varint(zigzag(n)), length)
+        } else {
+            (self.array.get(0), T::SIZE)
+        };
+        self.array.bytes = &self.array.bytes[length..];
+        self.array.len -= 1;
+        Some(element)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.array.len, Some(self.array.len))
+    }
+}
+
+impl<'a, T: ArrayElement> ExactSizeIterator for ArrayIter<'a, T> {}
+
+// The parts of the nbt data that should be parsed
+// Ex: `Filter::new(&["Level.Sections", "DataVersion"])`
+// Paths go through lists, so `Level.Sections.Y` keeps only the Y of each section
+#[derive(Debug)]
+pub enum Filter {
+    // Everything is wanted
+    All,
+    // Only these keys of the compound are wanted
+    Keys(HashMap<String, Filter>),
+}
+
+impl Filter {
+    pub fn new(paths: &[&str]) -> Filter {
+        let mut filter = Filter::Keys(HashMap::new());
+        for path in paths {
+            let mut node = &mut filter;
+            for key in path.split('.') {
+                node = match node {
+                    // The whole parent is already wanted
+                    Filter::All => break,
+                    Filter::Keys(keys) => keys
+                        .entry(key.to_owned())
+                        .or_insert_with(|| Filter::Keys(HashMap::new())),
+                };
+            }
+            *node = Filter::All;
+        }
+        filter
+    }
+
+    fn get(&self, key: &str) -> Option<&Filter> {
+        match self {
+            Filter::All => Some(self),
+            Filter::Keys(keys) => keys.get(key),
+        }
+    }
+}
+
+// Parses nbt data without copying it
+// Strings and arrays in the returned tags are borrowed from the parsed bytes
+pub struct NBTParser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    encoding: Encoding,
+    header: Header,
+}
+
+impl<'a> NBTParser<'a> {
+    // Java edition nbt
+    pub fn new(bytes: &'a [u8]) -> NBTParser<'a> {
+        NBTParser::with_format(bytes, Encoding::BigEndian, Header::Named)
+    }
+
+    // Ex: `NBTParser::with_format(&bytes, Encoding::LittleEndian, Header::LevelDat)`
+    // For the level.dat of a Bedrock world
+    pub fn with_format(bytes: &'a [u8], encoding: Encoding, header: Header) -> NBTParser<'a> {
         NBTParser {
-            bytes: Cursor::new(bytes),
+            bytes,
+            position: 0,
+            encoding,
+            header,
         }
     }
 
-    pub fn read_id(&mut self, id: u8) -> io::Result<Tag> {
+    #[allow(dead_code)]
+    pub fn read_id(&mut self, id: u8) -> Result<TagRef<'a>> {
+        self.read_filtered(id, &Filter::All)
+    }
+
+    fn read_filtered(&mut self, id: u8, filter: &Filter) -> Result<TagRef<'a>> {
         Ok(match id {
-            1 => self.read_byte()?,
-            2 => self.read_short()?,
-            3 => self.read_int()?,
-            4 => self.read_long()?,
-            5 => self.read_float()?,
-            6 => self.read_double()?,
-            7 => self.read_byte_array()?,
-            8 => self.read_string()?,
-            9 => self.read_list()?,
-            10 => self.read_compound()?,
-            11 => self.read_int_array()?,
-            12 => self.read_long_array()?,
-            _ => panic!("Unknown tag type"),
+            1 => TagRef::Byte(self.read_u8()? as i8),
+            2 => TagRef::Short(self.read_i16()?),
+            3 => TagRef::Int(self.read_i32()?),
+            4 => TagRef::Long(self.read_i64()?),
+            5 => TagRef::Float(self.read_f32()?),
+            6 => TagRef::Double(self.read_f64()?),
+            7 => TagRef::ByteArray(self.read_array()?),
+            8 => TagRef::String(self.read_mutf8()?),
+            9 => self.read_list(filter)?,
+            10 => self.read_compound_body(true, filter)?,
+            11 => TagRef::IntArray(self.read_array()?),
+            12 => TagRef::LongArray(self.read_array()?),
+            _ => return Err(Error::new(ErrorKind::UnknownTag(id), self.position())),
         })
     }
 
     // At the root of each file there is a Compound
     // We can use this function to recursively parse any file
-    pub fn read_compound(&mut self) -> io::Result<Tag> {
-        let mut comp = Compound::new();
-        while let Ok(tag_id) = self.bytes.read_u8() {
+    // The end of the data is also accepted as the end of this compound
+    #[allow(dead_code)]
+    pub fn read_compound(&mut self) -> Result<TagRef<'a>> {
+        self.read_compound_filtered(&Filter::All)
+    }
+
+    // Like `read_compound`, but the tags not wanted by the filter are skipped
+    // The filter starts from the content of the root compound
+    pub fn read_compound_filtered(&mut self, filter: &Filter) -> Result<TagRef<'a>> {
+        if self.header == Header::LevelDat && self.position == 0 {
+            self.read_level_dat_header()?;
+        }
+        self.read_compound_body(false, filter)
+    }
+
+    // Only the data after the header is parsed
+    // The version of the format is not needed to parse it
+    fn read_level_dat_header(&mut self) -> Result<()> {
+        let _version = LittleEndian::read_i32(self.take(4)?);
+        let start = self.position();
+        let length = LittleEndian::read_i32(self.take(4)?);
+        if length < 0 || length as usize > self.bytes.len() - self.position {
+            return Err(Error::new(ErrorKind::InvalidLength(length), start));
+        }
+        self.bytes = &self.bytes[..self.position + length as usize];
+        Ok(())
+    }
+
+    // Inside of a file a compound must always be closed with an End tag
+    fn read_compound_body(&mut self, nested: bool, filter: &Filter) -> Result<TagRef<'a>> {
+        let mut comp = CompoundRef::new();
+        loop {
+            let tag_id = match self.read_u8() {
+                Ok(tag_id) => tag_id,
+                Err(_) if !nested => break,
+                Err(err) => return Err(err),
+            };
+
             // The end of the compound
             if tag_id == 0 {
-                return Ok(Tag::Compound(comp));
+                break;
+            }
+            if tag_id > 12 {
+                return Err(Error::new(
+                    ErrorKind::UnknownTag(tag_id),
+                    self.position() - 1,
+                ));
             }
 
             // Get the name of the field we are about to read
-            let name = self.read_name()?;
-            comp.insert(name, self.read_id(tag_id)?);
+            let name = if !nested && self.header == Header::Nameless {
+                Cow::Borrowed("")
+            } else {
+                self.read_name()?
+            };
+            // The filter doesn't apply to the name of the root compound
+            let wanted = if nested {
+                filter.get(&name)
+            } else {
+                Some(filter)
+            };
+            match wanted {
+                Some(filter) => {
+                    let tag = self
+                        .read_filtered(tag_id, filter)
+                        .map_err(|err| err.in_key(&name))?;
+                    comp.insert(name, tag);
+                }
+                None => self.skip_id(tag_id).map_err(|err| err.in_key(&name))?,
+            }
         }
 
-        Ok(Tag::Compound(comp))
+        Ok(TagRef::Compound(comp))
     }
 
-    // Reads a tag name
-    fn read_name(&mut self) -> io::Result<String> {
-        let name_length = self.bytes.read_u16::<BigEndian>()?;
-        let mut name = vec![0; name_length as usize];
-        self.bytes.read_exact(&mut name)?;
-        Ok(String::from_utf8(name).expect("Could not read name as utf8"))
+    // Moves past a tag without reading it
+    // Numbers still need to be read, as they can be varints
+    fn skip_id(&mut self, id: u8) -> Result<()> {
+        match id {
+            1 => self.take(1).map(|_| ()),
+            2 => self.read_i16().map(|_| ()),
+            3 => self.read_i32().map(|_| ()),
+            4 => self.read_i64().map(|_| ()),
+            5 => self.read_f32().map(|_| ()),
+            6 => self.read_f64().map(|_| ()),
+            7 => self.read_array::<i8>().map(|_| ()),
+            8 => self.read_string_bytes().map(|_| ()),
+            9 => self.skip_list(),
+            10 => self.skip_compound(),
+            11 => self.read_array::<i32>().map(|_| ()),
+            12 => self.read_array::<i64>().map(|_| ()),
+            _ => Err(Error::new(ErrorKind::UnknownTag(id), self.position())),
+        }
     }
 
-    fn read_byte(&mut self) -> io::Result<Tag> {
-        Ok(Tag::Byte(self.bytes.read_i8()?))
+    fn skip_list(&mut self) -> Result<()> {
+        let (id, length) = self.read_list_header()?;
+        for i in 0..length {
+            self.skip_id(id).map_err(|err| err.in_index(i as i32))?;
+        }
+        Ok(())
     }
 
-    fn read_short(&mut self) -> io::Result<Tag> {
-        Ok(Tag::Short(self.bytes.read_i16::<BigEndian>()?))
+    fn skip_compound(&mut self) -> Result<()> {
+        loop {
+            let tag_id = self.read_u8()?;
+            if tag_id == 0 {
+                return Ok(());
+            }
+
+            let name = self.read_string_bytes()?;
+            // The name is only decoded if it is needed for the error
+            self.skip_id(tag_id)
+                .map_err(|err| err.in_key(&String::from_utf8_lossy(name)))?;
+        }
     }
 
-    fn read_int(&mut self) -> io::Result<Tag> {
-        Ok(Tag::Int(self.bytes.read_i32::<BigEndian>()?))
+    // Moves past the next `length` bytes and returns them
+    fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        if length > self.bytes.len() - self.position {
+            return Err(self.eof());
+        }
+        let bytes = &self.bytes[self.position..self.position + length];
+        self.position += length;
+        Ok(bytes)
     }
 
-    fn read_long(&mut self) -> io::Result<Tag> {
-        Ok(Tag::Long(self.bytes.read_i64::<BigEndian>()?))
+    fn position(&self) -> u64 {
+        self.position as u64
     }
 
-    fn read_float(&mut self) -> io::Result<Tag> {
-        Ok(Tag::Float(self.bytes.read_f32::<BigEndian>()?))
+    fn eof(&self) -> Error {
+        Error::new(ErrorKind::UnexpectedEof, self.position())
     }
 
-    fn read_double(&mut self) -> io::Result<Tag> {
-        Ok(Tag::Double(self.bytes.read_f64::<BigEndian>()?))
+    fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
     }
 
-    fn read_byte_array(&mut self) -> io::Result<Tag> {
-        let length = self.bytes.read_i32::<BigEndian>()?;
+    // Reads an unsigned varint for a `size` bytes long number
+    fn read_varint(&mut self, size: usize) -> Result<u64> {
+        let (number, length) = read_varint(&self.bytes[self.position..], varint_length(size))
+            .map_err(|kind| Error::new(kind, self.position()))?;
+        self.position += length;
+        Ok(number)
+    }
+
+    fn read_i16(&mut self) -> Result<i16> {
+        let bytes = self.take(2)?;
+        Ok(match self.encoding {
+            Encoding::BigEndian => BigEndian::read_i16(bytes),
+            _ => LittleEndian::read_i16(bytes),
+        })
+    }
 
-        let mut bytes = vec![0; length as usize];
-        for byte in &mut bytes {
-            *byte = self.bytes.read_i8()?;
+    fn read_i32(&mut self) -> Result<i32> {
+        Ok(match self.encoding {
+            Encoding::BigEndian => BigEndian::read_i32(self.take(4)?),
+            Encoding::LittleEndian => LittleEndian::read_i32(self.take(4)?),
+            Encoding::Network => zigzag(self.read_varint(4)?) as i32,
+        })
+    }
+
+    fn read_i64(&mut self) -> Result<i64> {
+        Ok(match self.encoding {
+            Encoding::BigEndian => BigEndian::read_i64(self.take(8)?),
+            Encoding::LittleEndian => LittleEndian::read_i64(self.take(8)?),
+            Encoding::Network => zigzag(self.read_varint(8)?),
+        })
+    }
+
+    fn read_f32(&mut self) -> Result<f32> {
+        let bytes = self.take(4)?;
+        Ok(match self.encoding {
+            Encoding::BigEndian => BigEndian::read_f32(bytes),
+            _ => LittleEndian::read_f32(bytes),
+        })
+    }
+
+    fn read_f64(&mut self) -> Result<f64> {
+        let bytes = self.take(8)?;
+        Ok(match self.encoding {
+            Encoding::BigEndian => BigEndian::read_f64(bytes),
+            _ => LittleEndian::read_f64(bytes),
+        })
+    }
+
+    // Reads an array length, checking that there are enough bytes left
+    // For `size` bytes long elements, so that broken data can't allocate huge vectors
+    fn read_length(&mut self, size: usize) -> Result<usize> {
+        let start = self.position();
+        let length = self.read_i32()?;
+        if length < 0 || length as usize * size > self.bytes.len() - self.position {
+            return Err(Error::new(ErrorKind::InvalidLength(length), start));
         }
+        Ok(length as usize)
+    }
+
+    // Reads the bytes of a string prefixed by its length
+    fn read_string_bytes(&mut self) -> Result<&'a [u8]> {
+        let length = match self.encoding {
+            Encoding::BigEndian => usize::from(BigEndian::read_u16(self.take(2)?)),
+            Encoding::LittleEndian => usize::from(LittleEndian::read_u16(self.take(2)?)),
+            Encoding::Network => self.read_varint(4)? as usize,
+        };
+        self.take(length)
+    }
 
-        Ok(Tag::ByteArray(bytes))
+    // Reads a string prefixed by its length, saved as modified utf8
+    // Only strings which are not also valid utf8 need to be copied
+    fn read_mutf8(&mut self) -> Result<Cow<'a, str>> {
+        let start = self.position();
+        let string = self.read_string_bytes()?;
+        mutf8::decode(string).ok_or_else(|| Error::new(ErrorKind::InvalidString, start))
     }
 
-    fn read_string(&mut self) -> io::Result<Tag> {
-        let length = self.bytes.read_u16::<BigEndian>()?;
-        let mut string = vec![0; length as usize];
-        self.bytes.read_exact(&mut string)?;
-        let string = String::from_utf8(string).expect("Could not read string as utf8");
+    // Reads a tag name
+    fn read_name(&mut self) -> Result<Cow<'a, str>> {
+        self.read_mutf8()
+    }
+
+    // The elements are left in the parsed bytes, they are decoded when they are accessed
+    fn read_array<T: ArrayElement>(&mut self) -> Result<ArrayRef<'a, T>> {
+        let varints = self.encoding == Encoding::Network && T::VARINT;
+        // Each varint is at least one byte long
+        let len = self.read_length(if varints { 1 } else { T::SIZE })?;
+        let start = self.position;
+        if varints {
+            for i in 0..len {
+                self.read_varint(T::SIZE)
+                    .map_err(|err| err.in_index(i as i32))?;
+            }
+        } else {
+            self.take(len * T::SIZE)?;
+        }
 
-        Ok(Tag::String(string))
+        Ok(ArrayRef {
+            bytes: &self.bytes[start..self.position],
+            len,
+            encoding: self.encoding,
+            element: PhantomData,
+        })
     }
 
-    fn read_list(&mut self) -> io::Result<Tag> {
-        let id = self.bytes.read_u8()?;
-        let length = self.bytes.read_i32::<BigEndian>()?;
+    // Reads the type and the length of a list
+    fn read_list_header(&mut self) -> Result<(u8, usize)> {
+        let id = self.read_u8()?;
+        let id_offset = self.position() - 1;
+        // Every tag is at least one byte long
+        let length = self.read_length(if id == 0 { 0 } else { 1 })?;
+        // Empty lists are saved with the End tag as their type
+        if id > 12 || (id == 0 && length > 0) {
+            return Err(Error::new(ErrorKind::UnknownTag(id), id_offset));
+        }
+        Ok((id, length))
+    }
+
+    // The filter applies to every element of the list
+    fn read_list(&mut self, filter: &Filter) -> Result<TagRef<'a>> {
+        let (id, length) = self.read_list_header()?;
 
-        let mut tags = Vec::new();
-        for _ in 0..length {
-            tags.push(self.read_id(id)?);
+        let mut tags = Vec::with_capacity(length);
+        for i in 0..length {
+            let tag = self
+                .read_filtered(id, filter)
+                .map_err(|err| err.in_index(i as i32))?;
+            tags.push(tag);
         }
 
-        Ok(Tag::List(tags))
+        Ok(TagRef::List(tags))
     }
+}
 
-    fn read_int_array(&mut self) -> io::Result<Tag> {
-        let length = self.bytes.read_i32::<BigEndian>()?;
+#[cfg(test)]
+mod tests {
+    use super::writer::{self, Compression};
+    use super::*;
 
-        let mut bytes = vec![0; length as usize];
-        for byte in &mut bytes {
-            *byte = self.bytes.read_i32::<BigEndian>()?;
+    // A chunk with three sections, each with a palette of one block
+    fn sections_nbt() -> Vec<u8> {
+        let sections = (0..3)
+            .map(|y| {
+                let mut block = Compound::new();
+                block.insert("Name".to_owned(), Tag::String("minecraft:stone".to_owned()));
+                let mut section = Compound::new();
+                section.insert("Y".to_owned(), Tag::Byte(y));
+                section.insert("Palette".to_owned(), Tag::List(vec![Tag::Compound(block)]));
+                Tag::Compound(section)
+            })
+            .collect();
+        let mut level = Compound::new();
+        level.insert("Sections".to_owned(), Tag::List(sections));
+        let mut comp = Compound::new();
+        comp.insert("Level".to_owned(), Tag::Compound(level));
+        writer::to_bytes("", &comp, Compression::None).unwrap()
+    }
+
+    // The position of the byte just after the `n`th (from 0) occurrence of `pattern`
+    fn find_after(bytes: &[u8], pattern: &[u8], n: usize) -> usize {
+        bytes
+            .windows(pattern.len())
+            .enumerate()
+            .filter(|(_, window)| *window == pattern)
+            .nth(n)
+            .unwrap()
+            .0
+            + pattern.len()
+    }
+
+    #[test]
+    fn error_offset_and_path() {
+        // The type of the last palette is broken
+        let mut bytes = sections_nbt();
+        let id = find_after(&bytes, b"Palette", 2);
+        bytes[id] = 13;
+        let err = NBTParser::new(&bytes).read_compound().unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::UnknownTag(13)));
+        assert_eq!(err.offset, Some(id as u64));
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "unknown tag type 13 at byte {} in `Level.Sections[2].Palette`",
+                id
+            )
+        );
+
+        // The data ends in the name of the block of the last palette
+        let bytes = sections_nbt();
+        let end = find_after(&bytes, b"minecraft:", 2);
+        let err = NBTParser::new(&bytes[..end]).read_compound().unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::UnexpectedEof));
+        assert!(err.to_string().contains(&format!("at byte {}", end - 10)));
+        assert!(err
+            .to_string()
+            .ends_with("in `Level.Sections[2].Palette[0].Name`"));
+    }
+
+    // A chunk whose sections have their Y after tags of every kind
+    fn filtered_nbt() -> Vec<u8> {
+        let sections = (0..3)
+            .map(|y| {
+                let mut props = Compound::new();
+                props.insert("facing".to_owned(), Tag::String("north".to_owned()));
+                let mut block = Compound::new();
+                block.insert("Name".to_owned(), Tag::String("minecraft:chest".to_owned()));
+                block.insert("Properties".to_owned(), Tag::Compound(props));
+                let mut section = Compound::new();
+                section.insert("Palette".to_owned(), Tag::List(vec![Tag::Compound(block)]));
+                section.insert(
+                    "Nested".to_owned(),
+                    Tag::List(vec![
+                        Tag::List(vec![Tag::Short(1), Tag::Short(2)]),
+                        Tag::List(vec![]),
+                    ]),
+                );
+                section.insert("Blocks".to_owned(), Tag::ByteArray(vec![1; 7]));
+                section.insert("Light".to_owned(), Tag::IntArray(vec![-1; 3]));
+                section.insert("BlockStates".to_owned(), Tag::LongArray(vec![5; 2]));
+                section.insert("Scale".to_owned(), Tag::Double(0.5));
+                section.insert("Y".to_owned(), Tag::Byte(y));
+                Tag::Compound(section)
+            })
+            .collect();
+        let mut level = Compound::new();
+        level.insert("Sections".to_owned(), Tag::List(sections));
+        level.insert("Entities".to_owned(), Tag::List(vec![]));
+        let mut comp = Compound::new();
+        comp.insert("Level".to_owned(), Tag::Compound(level));
+        comp.insert("DataVersion".to_owned(), Tag::Int(1343));
+        writer::to_bytes("", &comp, Compression::None).unwrap()
+    }
+
+    #[test]
+    fn filtered_parsing() {
+        let filter = Filter::new(&["Level.Sections.Y"]);
+        let bytes = filtered_nbt();
+        let root = NBTParser::new(&bytes)
+            .read_compound_filtered(&filter)
+            .unwrap();
+
+        // Only the path to the Y of the sections is kept
+        let root = root.as_compound().unwrap()[""].as_compound().unwrap();
+        assert_eq!(root.keys().collect::<Vec<_>>(), ["Level"]);
+        let level = root["Level"].as_compound().unwrap();
+        assert_eq!(level.keys().collect::<Vec<_>>(), ["Sections"]);
+        let sections = match &level["Sections"] {
+            TagRef::List(sections) => sections,
+            tag => panic!("expected a list, got {:?}", tag),
+        };
+        assert_eq!(sections.len(), 3);
+        for (y, section) in sections.iter().enumerate() {
+            let section = section.as_compound().unwrap();
+            assert_eq!(section.keys().collect::<Vec<_>>(), ["Y"]);
+            assert_eq!(section["Y"], TagRef::Byte(y as i8));
         }
 
-        Ok(Tag::IntArray(bytes))
+        // A skipped tag which ends too early is still an error
+        let end = find_after(&bytes, b"facing", 1) + 3;
+        let err = NBTParser::new(&bytes[..end])
+            .read_compound_filtered(&filter)
+            .unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::UnexpectedEof));
+        assert_eq!(err.path, "Level.Sections[1].Palette[0].Properties.facing");
     }
 
-    fn read_long_array(&mut self) -> io::Result<Tag> {
-        let length = self.bytes.read_i32::<BigEndian>()?;
+    // The root compound of a parsed file, without its name
+    fn parse(bytes: &[u8], encoding: Encoding, header: Header) -> Result<Tag> {
+        let root = NBTParser::with_format(bytes, encoding, header).read_compound()?;
+        match Tag::from(root) {
+            Tag::Compound(mut root) => Ok(root.swap_remove("").unwrap()),
+            tag => panic!("expected a compound, got {:?}", tag),
+        }
+    }
+
+    #[test]
+    fn bedrock_level_dat() {
+        let mut body = vec![10, 0, 0];
+        body.extend([3, 14, 0]);
+        body.extend(b"StorageVersion");
+        body.extend(10_i32.to_le_bytes());
+        body.extend([8, 9, 0]);
+        body.extend(b"LevelName");
+        body.extend([5, 0]);
+        body.extend(b"world");
+        body.extend([4, 4, 0]);
+        body.extend(b"Time");
+        body.extend((-2_i64).to_le_bytes());
+        body.push(0);
+
+        let mut bytes = Vec::new();
+        bytes.extend(10_i32.to_le_bytes());
+        bytes.extend((body.len() as i32).to_le_bytes());
+        bytes.extend(&body);
+        // The data after the length given in the header is not parsed
+        bytes.extend([0xFF; 4]);
+
+        let mut expected = Compound::new();
+        expected.insert("StorageVersion".to_owned(), Tag::Int(10));
+        expected.insert("LevelName".to_owned(), Tag::String("world".to_owned()));
+        expected.insert("Time".to_owned(), Tag::Long(-2));
+        let level = parse(&bytes, Encoding::LittleEndian, Header::LevelDat).unwrap();
+        assert_eq!(level, Tag::Compound(expected));
+
+        // A length longer than the file
+        bytes[4..8].copy_from_slice(&(body.len() as i32 + 5).to_le_bytes());
+        let err = parse(&bytes, Encoding::LittleEndian, Header::LevelDat).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::InvalidLength(_)));
+        assert_eq!(err.offset, Some(4));
+        bytes[4..8].copy_from_slice(&(-1_i32).to_le_bytes());
+        let err = parse(&bytes, Encoding::LittleEndian, Header::LevelDat).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::InvalidLength(-1)));
+    }
 
-        let mut bytes = vec![0; length as usize];
-        for byte in &mut bytes {
-            *byte = self.bytes.read_i64::<BigEndian>()?;
+    #[test]
+    fn network_nbt() {
+        let mut bytes = vec![10, 0];
+        // -1 and -300 as zigzag varints
+        bytes.extend([3, 3]);
+        bytes.extend(b"int");
+        bytes.push(0x01);
+        bytes.extend([4, 4]);
+        bytes.extend(b"long");
+        bytes.extend([0xD7, 0x04]);
+        // A string whose length needs two bytes
+        bytes.extend([8, 6]);
+        bytes.extend(b"string");
+        bytes.extend([0x82, 0x01]);
+        bytes.extend([b'x'; 130]);
+        // The length and the elements of int arrays are zigzag varints too
+        bytes.extend([11, 4]);
+        bytes.extend(b"ints");
+        bytes.push(0x06);
+        bytes.extend([0x01, 0x80, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F]);
+        // But not the ones of byte arrays, nor shorts
+        bytes.extend([7, 5]);
+        bytes.extend(b"bytes");
+        bytes.extend([0x04, 0xFF, 0x7F]);
+        bytes.extend([2, 5]);
+        bytes.extend(b"short");
+        bytes.extend([0x00, 0x80]);
+        bytes.push(0);
+
+        let mut expected = Compound::new();
+        expected.insert("int".to_owned(), Tag::Int(-1));
+        expected.insert("long".to_owned(), Tag::Long(-300));
+        expected.insert("string".to_owned(), Tag::String("x".repeat(130)));
+        expected.insert("ints".to_owned(), Tag::IntArray(vec![-1, 64, i32::MIN]));
+        expected.insert("bytes".to_owned(), Tag::ByteArray(vec![-1, 127]));
+        expected.insert("short".to_owned(), Tag::Short(i16::MIN));
+        let comp = parse(&bytes, Encoding::Network, Header::Named).unwrap();
+        assert_eq!(comp, Tag::Compound(expected));
+    }
+
+    #[test]
+    fn invalid_varints() {
+        // An int can't take more than 5 bytes
+        let mut bytes = vec![10, 0, 3, 1, b'n'];
+        bytes.extend([0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]);
+        bytes.push(0);
+        let err = parse(&bytes, Encoding::Network, Header::Named).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::InvalidVarint));
+        assert_eq!(err.offset, Some(5));
+        assert_eq!(err.path, "n");
+
+        // Nor a long more than 10 bytes
+        let mut bytes = vec![10, 0, 4, 1, b'n'];
+        bytes.extend([0x80; 10]);
+        bytes.extend([0x01, 0]);
+        let err = parse(&bytes, Encoding::Network, Header::Named).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::InvalidVarint));
+
+        // The elements of arrays are checked when the array is parsed
+        let bytes = [
+            10, 0, 11, 1, b'a', 0x02, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01, 0,
+        ];
+        let err = parse(&bytes, Encoding::Network, Header::Named).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::InvalidVarint));
+        assert_eq!(err.path, "a[0]");
+    }
+
+    #[test]
+    fn nameless_root() {
+        let mut comp = Compound::new();
+        comp.insert("id".to_owned(), Tag::Short(-5));
+        let bytes = writer::to_bytes("", &comp, Compression::None).unwrap();
+        // The same data, without the length of the empty name of the root
+        let nameless: Vec<u8> = [&bytes[..1], &bytes[3..]].concat();
+
+        let root = parse(&nameless, Encoding::BigEndian, Header::Nameless).unwrap();
+        assert_eq!(root, Tag::Compound(comp));
+        // Parsed as named, the first tag is read as the name of the root
+        assert!(parse(&nameless, Encoding::BigEndian, Header::Named).is_err());
+    }
+
+    #[test]
+    fn key_order() {
+        // The keys are neither sorted nor in the order a hash map would give
+        let keys = ["z", "DataVersion", "a", "Level", "m", "b"];
+        let mut inner = Compound::new();
+        for key in keys.iter().rev() {
+            inner.insert(key.to_string(), Tag::Byte(0));
         }
+        let mut comp = Compound::new();
+        for key in keys {
+            comp.insert(key.to_owned(), Tag::Compound(inner.clone()));
+        }
+        let bytes = writer::to_bytes("", &comp, Compression::None).unwrap();
+
+        let root = NBTParser::new(&bytes).read_compound().unwrap();
+        let parsed = root.as_compound().unwrap()[""].as_compound().unwrap();
+        assert_eq!(parsed.keys().collect::<Vec<_>>(), keys);
+        let inner_keys: Vec<_> = parsed["m"].as_compound().unwrap().keys().collect();
+        assert_eq!(inner_keys, keys.iter().rev().collect::<Vec<_>>());
 
-        Ok(Tag::LongArray(bytes))
+        // Saving the parsed tags gives back the same bytes
+        let root = match Tag::from(root) {
+            Tag::Compound(root) => root,
+            tag => panic!("expected a compound, got {:?}", tag),
+        };
+        let saved = writer::to_bytes("", root[""].as_compound().unwrap(), Compression::None);
+        assert_eq!(saved.unwrap(), bytes);
     }
 }
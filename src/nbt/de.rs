@@ -0,0 +1,478 @@
+// Serde deserializer over an already parsed tree of tags
+// So that nbt structures can be read with `#[derive(Deserialize)]` structs
+
+use std::marker::PhantomData;
+
+use serde::de::{self, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::forward_to_deserialize_any;
+
+use super::ser::{BYTE_ARRAY, INT_ARRAY, LONG_ARRAY};
+use super::{ArrayElement, ArrayRef, Encoding, Error, Result, Tag, TagRef};
+
+impl de::Error for Error {
+    fn custom<T: std::fmt::Display>(msg: T) -> Error {
+        Error::custom(msg.to_string())
+    }
+}
+
+// Strings in the structure are borrowed from the tag where possible
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn from_tag<'de, T: de::Deserialize<'de>>(tag: &'de Tag) -> Result<T> {
+    T::deserialize(tag)
+}
+
+// Like `from_tag`, but arrays can also be borrowed as an `ArrayRef`
+pub fn from_tag_ref<'de, 'a: 'de, T: de::Deserialize<'de>>(tag: &'de TagRef<'a>) -> Result<T> {
+    T::deserialize(tag)
+}
+
+impl<'de> de::Deserializer<'de> for &'de Tag {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self {
+            Tag::Byte(n) => visitor.visit_i8(*n),
+            Tag::Short(n) => visitor.visit_i16(*n),
+            Tag::Int(n) => visitor.visit_i32(*n),
+            Tag::Long(n) => visitor.visit_i64(*n),
+            Tag::Float(n) => visitor.visit_f32(*n),
+            Tag::Double(n) => visitor.visit_f64(*n),
+            Tag::String(string) => visitor.visit_borrowed_str(string),
+            Tag::ByteArray(bytes) => visitor.visit_seq(SeqAccess::new(bytes.iter().copied())),
+            Tag::IntArray(ints) => visitor.visit_seq(SeqAccess::new(ints.iter().copied())),
+            Tag::LongArray(longs) => visitor.visit_seq(SeqAccess::new(longs.iter().copied())),
+            Tag::List(tags) => visitor.visit_seq(SeqAccess::new(tags.iter())),
+            Tag::Compound(comp) => visitor.visit_map(MapAccess::new(
+                comp.iter().map(|(name, tag)| (name.as_str(), tag)),
+            )),
+        }
+    }
+
+    // Booleans are saved as bytes
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self {
+            Tag::Byte(n) => visitor.visit_bool(*n != 0),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    // Missing fields are handled by serde, so a tag that exists is always Some
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_some(self)
+    }
+
+    // u8 is saved as a byte, so negative bytes are the numbers above 127
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self {
+            Tag::Byte(n) => visitor.visit_u8(*n as u8),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    // Arrays can't be borrowed from an owned tag, see the deserializer of `TagRef`
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        match name {
+            BYTE_ARRAY | INT_ARRAY | LONG_ARRAY => {
+                Err(de::Error::invalid_type(unexpected(self), &visitor))
+            }
+            _ => visitor.visit_newtype_struct(self),
+        }
+    }
+
+    // Enums are either a string with the name of the variant
+    // Or a compound with a single key, the name of the variant, and its content
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        match self {
+            Tag::String(string) => visitor.visit_enum(string.as_str().into_deserializer()),
+            Tag::Compound(comp) if comp.len() == 1 => {
+                let (variant, value) = comp.iter().next().unwrap();
+                visitor
+                    .visit_enum(EnumAccess { variant, value })
+                    .map_err(|err| err.in_key(variant))
+            }
+            _ => Err(de::Error::invalid_type(unexpected(self), &"an enum")),
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple
+        tuple_struct map struct identifier
+    }
+}
+
+// The same as the deserializer of `Tag`
+// Except that arrays are given as their raw bytes when an `ArrayRef` asks for them
+impl<'de, 'a: 'de> de::Deserializer<'de> for &'de TagRef<'a> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self {
+            TagRef::Byte(n) => visitor.visit_i8(*n),
+            TagRef::Short(n) => visitor.visit_i16(*n),
+            TagRef::Int(n) => visitor.visit_i32(*n),
+            TagRef::Long(n) => visitor.visit_i64(*n),
+            TagRef::Float(n) => visitor.visit_f32(*n),
+            TagRef::Double(n) => visitor.visit_f64(*n),
+            TagRef::String(string) => visitor.visit_borrowed_str(string),
+            TagRef::ByteArray(bytes) => visitor.visit_seq(SeqAccess::new(bytes.iter())),
+            TagRef::IntArray(ints) => visitor.visit_seq(SeqAccess::new(ints.iter())),
+            TagRef::LongArray(longs) => visitor.visit_seq(SeqAccess::new(longs.iter())),
+            TagRef::List(tags) => visitor.visit_seq(SeqAccess::new(tags.iter())),
+            TagRef::Compound(comp) => visitor.visit_map(MapAccess::new(
+                comp.iter().map(|(name, tag)| (&**name, tag)),
+            )),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self {
+            TagRef::Byte(n) => visitor.visit_bool(*n != 0),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self {
+            TagRef::Byte(n) => visitor.visit_u8(*n as u8),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        match (name, self) {
+            (BYTE_ARRAY, TagRef::ByteArray(array)) => visitor.visit_seq(RawArrayAccess::new(array)),
+            (INT_ARRAY, TagRef::IntArray(array)) => visitor.visit_seq(RawArrayAccess::new(array)),
+            (LONG_ARRAY, TagRef::LongArray(array)) => visitor.visit_seq(RawArrayAccess::new(array)),
+            (BYTE_ARRAY, _) | (INT_ARRAY, _) | (LONG_ARRAY, _) => {
+                Err(de::Error::invalid_type(unexpected_ref(self), &visitor))
+            }
+            _ => visitor.visit_newtype_struct(self),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        match self {
+            TagRef::String(string) => visitor.visit_enum((&**string).into_deserializer()),
+            TagRef::Compound(comp) if comp.len() == 1 => {
+                let (variant, value) = comp.iter().next().unwrap();
+                visitor
+                    .visit_enum(EnumAccess { variant, value })
+                    .map_err(|err| err.in_key(variant))
+            }
+            _ => Err(de::Error::invalid_type(unexpected_ref(self), &"an enum")),
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple
+        tuple_struct map struct identifier
+    }
+}
+
+fn unexpected(tag: &Tag) -> de::Unexpected<'_> {
+    match tag {
+        Tag::Byte(n) => de::Unexpected::Signed(i64::from(*n)),
+        Tag::Short(n) => de::Unexpected::Signed(i64::from(*n)),
+        Tag::Int(n) => de::Unexpected::Signed(i64::from(*n)),
+        Tag::Long(n) => de::Unexpected::Signed(*n),
+        Tag::Float(n) => de::Unexpected::Float(f64::from(*n)),
+        Tag::Double(n) => de::Unexpected::Float(*n),
+        Tag::String(string) => de::Unexpected::Str(string),
+        Tag::ByteArray(_) | Tag::IntArray(_) | Tag::LongArray(_) | Tag::List(_) => {
+            de::Unexpected::Seq
+        }
+        Tag::Compound(_) => de::Unexpected::Map,
+    }
+}
+
+fn unexpected_ref<'a>(tag: &'a TagRef<'_>) -> de::Unexpected<'a> {
+    match tag {
+        TagRef::Byte(n) => de::Unexpected::Signed(i64::from(*n)),
+        TagRef::Short(n) => de::Unexpected::Signed(i64::from(*n)),
+        TagRef::Int(n) => de::Unexpected::Signed(i64::from(*n)),
+        TagRef::Long(n) => de::Unexpected::Signed(*n),
+        TagRef::Float(n) => de::Unexpected::Float(f64::from(*n)),
+        TagRef::Double(n) => de::Unexpected::Float(*n),
+        TagRef::String(string) => de::Unexpected::Str(string),
+        TagRef::ByteArray(_) | TagRef::IntArray(_) | TagRef::LongArray(_) | TagRef::List(_) => {
+            de::Unexpected::Seq
+        }
+        TagRef::Compound(_) => de::Unexpected::Map,
+    }
+}
+
+// An array can only be borrowed from a `TagRef` of the same array type
+impl<'de: 'a, 'a, T: ArrayElement> de::Deserialize<'de> for ArrayRef<'a, T> {
+    fn deserialize<D: de::Deserializer<'de>>(d: D) -> std::result::Result<Self, D::Error> {
+        let name = match T::ID {
+            7 => BYTE_ARRAY,
+            11 => INT_ARRAY,
+            _ => LONG_ARRAY,
+        };
+        d.deserialize_newtype_struct(name, ArrayVisitor(PhantomData))
+    }
+}
+
+struct ArrayVisitor<T>(PhantomData<T>);
+
+impl<'de, T: ArrayElement> Visitor<'de> for ArrayVisitor<T> {
+    type Value = ArrayRef<'de, T>;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "an array tag borrowed from the parsed bytes")
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(
+        self,
+        mut seq: A,
+    ) -> std::result::Result<Self::Value, A::Error> {
+        let encoding: u8 = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let len: u64 = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        let bytes: &'de [u8] = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(2, &self))?;
+        let encoding = *ENCODINGS
+            .get(usize::from(encoding))
+            .ok_or_else(|| de::Error::custom("unknown array encoding"))?;
+
+        Ok(ArrayRef {
+            bytes,
+            len: len as usize,
+            encoding,
+            element: PhantomData,
+        })
+    }
+}
+
+// The order in which the encoding is given to `ArrayVisitor`
+const ENCODINGS: [Encoding; 3] = [
+    Encoding::BigEndian,
+    Encoding::LittleEndian,
+    Encoding::Network,
+];
+
+// Gives an array to `ArrayVisitor` as the sequence (encoding, length, bytes)
+struct RawArrayAccess<'de> {
+    encoding: u8,
+    len: u64,
+    bytes: &'de [u8],
+    index: usize,
+}
+
+impl<'de> RawArrayAccess<'de> {
+    fn new<T>(array: &ArrayRef<'de, T>) -> RawArrayAccess<'de> {
+        RawArrayAccess {
+            encoding: ENCODINGS
+                .iter()
+                .position(|encoding| *encoding == array.encoding)
+                .unwrap() as u8,
+            len: array.len as u64,
+            bytes: array.bytes,
+            index: 0,
+        }
+    }
+}
+
+impl<'de> de::SeqAccess<'de> for RawArrayAccess<'de> {
+    type Error = Error;
+
+    fn next_element_seed<S: DeserializeSeed<'de>>(&mut self, seed: S) -> Result<Option<S::Value>> {
+        self.index += 1;
+        match self.index {
+            1 => seed
+                .deserialize(self.encoding.into_deserializer())
+                .map(Some),
+            2 => seed.deserialize(self.len.into_deserializer()).map(Some),
+            3 => seed
+                .deserialize(de::value::BorrowedBytesDeserializer::new(self.bytes))
+                .map(Some),
+            _ => Ok(None),
+        }
+    }
+}
+
+// Lists and the three array types are all deserialized as sequences
+struct SeqAccess<I> {
+    elements: I,
+    index: i32,
+}
+
+impl<I> SeqAccess<I> {
+    fn new(elements: I) -> SeqAccess<I> {
+        SeqAccess { elements, index: 0 }
+    }
+}
+
+impl<'de, I> de::SeqAccess<'de> for SeqAccess<I>
+where
+    I: ExactSizeIterator,
+    I::Item: IntoDeserializer<'de, Error>,
+{
+    type Error = Error;
+
+    fn next_element_seed<S: DeserializeSeed<'de>>(&mut self, seed: S) -> Result<Option<S::Value>> {
+        match self.elements.next() {
+            Some(element) => {
+                let index = self.index;
+                self.index += 1;
+                seed.deserialize(element.into_deserializer())
+                    .map(Some)
+                    .map_err(|err| err.in_index(index))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.elements.len())
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for &'de Tag {
+    type Deserializer = &'de Tag;
+
+    fn into_deserializer(self) -> &'de Tag {
+        self
+    }
+}
+
+impl<'de, 'a: 'de> IntoDeserializer<'de, Error> for &'de TagRef<'a> {
+    type Deserializer = &'de TagRef<'a>;
+
+    fn into_deserializer(self) -> &'de TagRef<'a> {
+        self
+    }
+}
+
+// Works for the compounds of both `Tag` and `TagRef`
+struct MapAccess<'de, I, T> {
+    entries: I,
+    // The value of the last key, returned by `next_value_seed`
+    value: Option<(&'de str, &'de T)>,
+}
+
+impl<'de, I, T> MapAccess<'de, I, T> {
+    fn new(entries: I) -> MapAccess<'de, I, T> {
+        MapAccess {
+            entries,
+            value: None,
+        }
+    }
+}
+
+impl<'de, I, T> de::MapAccess<'de> for MapAccess<'de, I, T>
+where
+    I: ExactSizeIterator<Item = (&'de str, &'de T)>,
+    &'de T: de::Deserializer<'de, Error = Error>,
+{
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        match self.entries.next() {
+            Some((key, value)) => {
+                self.value = Some((key, value));
+                seed.deserialize(de::value::BorrowedStrDeserializer::new(key))
+                    .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        let (key, value) = self
+            .value
+            .take()
+            .expect("next_value_seed called before next_key_seed");
+        seed.deserialize(value).map_err(|err| err.in_key(key))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
+
+struct EnumAccess<'de, T> {
+    variant: &'de str,
+    value: &'de T,
+}
+
+impl<'de, T> de::EnumAccess<'de> for EnumAccess<'de, T>
+where
+    &'de T: de::Deserializer<'de, Error = Error>,
+{
+    type Error = Error;
+    type Variant = VariantAccess<'de, T>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, VariantAccess<'de, T>)> {
+        let variant = seed.deserialize(de::value::BorrowedStrDeserializer::new(self.variant))?;
+        Ok((variant, VariantAccess(self.value)))
+    }
+}
+
+struct VariantAccess<'de, T>(&'de T);
+
+impl<'de, T> de::VariantAccess<'de> for VariantAccess<'de, T>
+where
+    &'de T: de::Deserializer<'de, Error = Error>,
+{
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<S: DeserializeSeed<'de>>(self, seed: S) -> Result<S::Value> {
+        seed.deserialize(self.0)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_seq(self.0, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        de::Deserializer::deserialize_map(self.0, visitor)
+    }
+}
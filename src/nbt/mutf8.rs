@@ -0,0 +1,150 @@
+// Minecraft writes strings in the "modified utf8" format used by java
+// It differs from utf8 in two ways:
+// - The NUL character is written with the two bytes 0xC0 0x80
+// - Characters outside of the BMP (ex: emojis) are written as an utf16 surrogate pair
+//   where each of the two surrogates is written as a three byte character
+// Everything else is encoded exactly like in utf8
+
+use std::borrow::Cow;
+
+// Returns None if the bytes are not valid modified utf8
+pub fn decode(bytes: &[u8]) -> Option<Cow<'_, str>> {
+    // Most strings (block names, properties, ...) are also valid utf8
+    // And in that case they are decoded to the same characters
+    if let Ok(string) = std::str::from_utf8(bytes) {
+        return Some(Cow::Borrowed(string));
+    }
+
+    // Decode the utf16 code units, and then join the surrogate pairs
+    let mut units = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let first = u16::from(bytes[i]);
+        let (unit, length) = if first & 0x80 == 0 {
+            // 0xxxxxxx
+            (first, 1)
+        } else if first & 0xE0 == 0xC0 {
+            // 110xxxxx 10xxxxxx
+            let second = continuation(bytes.get(i + 1))?;
+            ((first & 0x1F) << 6 | second, 2)
+        } else if first & 0xF0 == 0xE0 {
+            // 1110xxxx 10xxxxxx 10xxxxxx
+            let second = continuation(bytes.get(i + 1))?;
+            let third = continuation(bytes.get(i + 2))?;
+            ((first & 0x0F) << 12 | second << 6 | third, 3)
+        } else {
+            // There are no four byte characters in modified utf8
+            return None;
+        };
+
+        units.push(unit);
+        i += length;
+    }
+
+    // Java strings can contain unpaired surrogates, which rust strings can't
+    // So they are replaced instead of making the whole string fail
+    Some(Cow::Owned(String::from_utf16_lossy(&units)))
+}
+
+// Returns the 6 bits of a continuation byte (10xxxxxx)
+fn continuation(byte: Option<&u8>) -> Option<u16> {
+    match byte {
+        Some(byte) if byte & 0xC0 == 0x80 => Some(u16::from(byte & 0x3F)),
+        _ => None,
+    }
+}
+
+pub fn encode(string: &str) -> Cow<'_, [u8]> {
+    // Strings without NUL and supplementary characters are the same as in utf8
+    if string.chars().all(|c| c != '\0' && (c as u32) < 0x10000) {
+        return Cow::Borrowed(string.as_bytes());
+    }
+
+    let mut bytes = Vec::with_capacity(string.len() + 2);
+    for c in string.chars() {
+        match c as u32 {
+            0 => bytes.extend_from_slice(&[0xC0, 0x80]),
+            0x01..=0xFFFF => {
+                let mut buf = [0; 3];
+                bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+            _ => {
+                let mut buf = [0; 2];
+                for unit in c.encode_utf16(&mut buf).iter() {
+                    let unit = *unit;
+                    bytes.push(0xE0 | (unit >> 12) as u8);
+                    bytes.push(0x80 | ((unit >> 6) & 0x3F) as u8);
+                    bytes.push(0x80 | (unit & 0x3F) as u8);
+                }
+            }
+        }
+    }
+
+    Cow::Owned(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(string: &str) -> Vec<u8> {
+        let bytes = encode(string).into_owned();
+        assert_eq!(decode(&bytes).as_deref(), Some(string));
+        bytes
+    }
+
+    #[test]
+    fn ascii_is_unchanged() {
+        assert_eq!(round_trip("minecraft:stone"), b"minecraft:stone");
+        assert_eq!(round_trip(""), b"");
+    }
+
+    #[test]
+    fn nul_uses_two_bytes() {
+        assert_eq!(round_trip("a\0b"), [b'a', 0xC0, 0x80, b'b']);
+    }
+
+    #[test]
+    fn supplementary_characters_use_surrogate_pairs() {
+        // U+1F600 is the surrogate pair D83D DE00
+        let bytes = round_trip("\u{1F600}");
+        assert_eq!(bytes, [0xED, 0xA0, 0xBD, 0xED, 0xB8, 0x80]);
+        assert_eq!(
+            round_trip("é\u{10FFFF}\0"),
+            "é".bytes()
+                .chain([0xED, 0xAF, 0xBF, 0xED, 0xBF, 0xBF, 0xC0, 0x80])
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn standard_utf8_is_accepted() {
+        assert_eq!(decode("\u{1F600}".as_bytes()).as_deref(), Some("\u{1F600}"));
+    }
+
+    #[test]
+    fn lone_surrogates_are_replaced() {
+        // A high surrogate without the low one, and a low surrogate alone
+        assert_eq!(
+            decode(&[b'a', 0xED, 0xA0, 0xBD]).as_deref(),
+            Some("a\u{FFFD}")
+        );
+        assert_eq!(
+            decode(&[0xED, 0xB8, 0x80, b'b']).as_deref(),
+            Some("\u{FFFD}b")
+        );
+    }
+
+    #[test]
+    fn invalid_bytes_are_errors() {
+        // Truncated characters
+        assert_eq!(decode(&[0xC0]), None);
+        assert_eq!(decode(&[b'a', 0xED, 0xA0]), None);
+        // A continuation byte without a first byte, and a bad continuation byte
+        assert_eq!(decode(&[0x80]), None);
+        assert_eq!(decode(&[0xE0, 0x41, 0x80]), None);
+        // Four byte characters don't exist in modified utf8
+        assert_eq!(decode(&[0xF0, 0x9F, 0x98]), None);
+        assert_eq!(decode(&[0xFF]), None);
+    }
+}
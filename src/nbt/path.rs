@@ -0,0 +1,281 @@
+// Nbt paths, the format used by minecraft commands like `/data get` to select tags
+// Ex: Level.Sections[0].Palette[{Name:"minecraft:chest"}]
+// - `key` or `"quoted key"` selects a tag of a compound
+// - `key{Name:"x"}` selects it only if it contains the given tags
+// - `[0]` selects an element of a list, negative indexes count from the end
+// - `[]` selects all the elements, `[{Name:"x"}]` only the ones which contain the given tags
+// - `{Name:"x"}` at the start selects the root only if it contains the given tags
+
+use std::str::FromStr;
+
+use super::snbt::SNBTParser;
+use super::{Compound, Error, ErrorKind, Result, Tag};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    MatchRoot(Compound),
+    Key(String),
+    MatchKey(String, Compound),
+    Index(i32),
+    All,
+    MatchElements(Compound),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NBTPath {
+    nodes: Vec<Node>,
+}
+
+// Nothing queries the chunks with paths yet, they are checked by the tests
+#[cfg_attr(not(test), allow(dead_code))]
+impl NBTPath {
+    // Returns every tag selected by the path, in the order they are found
+    // Only lists can be indexed, as the elements of arrays are not tags
+    pub fn get<'a>(&self, tag: &'a Tag) -> Vec<&'a Tag> {
+        let mut tags = vec![tag];
+        for node in &self.nodes {
+            tags = tags.into_iter().flat_map(|tag| select(node, tag)).collect();
+        }
+        tags
+    }
+}
+
+fn select<'a>(node: &Node, tag: &'a Tag) -> Vec<&'a Tag> {
+    match (node, tag) {
+        (Node::MatchRoot(pattern), Tag::Compound(comp)) => {
+            if contains(comp, pattern) {
+                vec![tag]
+            } else {
+                vec![]
+            }
+        }
+        (Node::Key(key), Tag::Compound(comp)) => comp.get(key).into_iter().collect(),
+        (Node::MatchKey(key, pattern), Tag::Compound(comp)) => match comp.get(key) {
+            Some(child @ Tag::Compound(child_comp)) if contains(child_comp, pattern) => {
+                vec![child]
+            }
+            _ => vec![],
+        },
+        (Node::Index(index), Tag::List(tags)) => {
+            let index = if *index < 0 {
+                tags.len() as i64 + i64::from(*index)
+            } else {
+                i64::from(*index)
+            };
+            if index >= 0 {
+                tags.get(index as usize).into_iter().collect()
+            } else {
+                vec![]
+            }
+        }
+        (Node::All, Tag::List(tags)) => tags.iter().collect(),
+        (Node::MatchElements(pattern), Tag::List(tags)) => tags
+            .iter()
+            .filter(|tag| match tag {
+                Tag::Compound(comp) => contains(comp, pattern),
+                _ => false,
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+// Checks that every tag in the pattern is also in the compound
+// Like in minecraft, a list in the pattern matches if each of its elements is in the list
+fn contains(comp: &Compound, pattern: &Compound) -> bool {
+    pattern
+        .iter()
+        .all(|(key, expected)| comp.get(key).is_some_and(|tag| matches(tag, expected)))
+}
+
+fn matches(tag: &Tag, pattern: &Tag) -> bool {
+    match (tag, pattern) {
+        (Tag::Compound(comp), Tag::Compound(pattern)) => contains(comp, pattern),
+        (Tag::List(tags), Tag::List(pattern)) if pattern.is_empty() => tags.is_empty(),
+        (Tag::List(tags), Tag::List(pattern)) => pattern
+            .iter()
+            .all(|expected| tags.iter().any(|tag| matches(tag, expected))),
+        _ => tag == pattern,
+    }
+}
+
+impl FromStr for NBTPath {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<NBTPath> {
+        let mut parser = SNBTParser::new(text);
+        let error = |parser: &SNBTParser, message| {
+            Error::new(ErrorKind::InvalidPath(message), parser.position() as u64)
+        };
+
+        let mut nodes = Vec::new();
+        if parser.peek() == Some('{') {
+            nodes.push(Node::MatchRoot(read_pattern(&mut parser)?));
+        }
+
+        while let Some(c) = parser.peek() {
+            match c {
+                '[' => {
+                    parser.next();
+                    let node = match parser.peek() {
+                        Some(']') => Node::All,
+                        Some('{') => Node::MatchElements(read_pattern(&mut parser)?),
+                        _ => {
+                            let start = parser.position();
+                            while parser
+                                .peek()
+                                .is_some_and(|c| c == '-' || c.is_ascii_digit())
+                            {
+                                parser.next();
+                            }
+                            let index = text[start..parser.position()]
+                                .parse()
+                                .map_err(|_| error(&parser, "expected an index"))?;
+                            Node::Index(index)
+                        }
+                    };
+                    if parser.next() != Some(']') {
+                        return Err(error(&parser, "expected ']'"));
+                    }
+                    nodes.push(node);
+                }
+                // Keys after the first one are separated by a dot
+                '.' if !nodes.is_empty() => {
+                    parser.next();
+                    nodes.push(read_key(&mut parser, text)?);
+                }
+                _ if nodes.is_empty() => nodes.push(read_key(&mut parser, text)?),
+                _ => return Err(error(&parser, "expected '.' or '['")),
+            }
+        }
+
+        if nodes.is_empty() {
+            return Err(error(&parser, "the path is empty"));
+        }
+        Ok(NBTPath { nodes })
+    }
+}
+
+// Reads a key, which can be followed by the tags it should contain
+fn read_key(parser: &mut SNBTParser, text: &str) -> Result<Node> {
+    let key = match parser.peek() {
+        Some('"') | Some('\'') => parser.read_quoted()?,
+        _ => {
+            let start = parser.position();
+            while parser.peek().is_some_and(is_unquoted) {
+                parser.next();
+            }
+            if start == parser.position() {
+                return Err(Error::new(
+                    ErrorKind::InvalidPath("expected a key"),
+                    start as u64,
+                ));
+            }
+            text[start..parser.position()].to_owned()
+        }
+    };
+
+    if parser.peek() == Some('{') {
+        Ok(Node::MatchKey(key, read_pattern(parser)?))
+    } else {
+        Ok(Node::Key(key))
+    }
+}
+
+// Keys in paths can contain more characters than in snbt, like ':'
+fn is_unquoted(c: char) -> bool {
+    !c.is_whitespace() && !"\"'[]{}.".contains(c)
+}
+
+fn read_pattern(parser: &mut SNBTParser) -> Result<Compound> {
+    match parser.read_compound()? {
+        Tag::Compound(comp) => Ok(comp),
+        _ => unreachable!(),
+    }
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+impl Tag {
+    // Ex: `tag.query("Level.Sections[0].Palette[{Name:\"minecraft:chest\"}]")`
+    pub fn query(&self, path: &str) -> Result<Vec<&Tag>> {
+        Ok(path.parse::<NBTPath>()?.get(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk() -> Tag {
+        r#"{
+            Version: 1,
+            "a.b": 2,
+            Level: {
+                Sections: [
+                    {Y: 0b, Palette: [
+                        {Name: "minecraft:stone"},
+                        {Name: "minecraft:chest", facing: "north"}
+                    ]},
+                    {Y: 1b, Palette: [{Name: "minecraft:chest", facing: "south"}]}
+                ]
+            },
+            Items: [{id: "a", Tags: [1, 2, 3]}, {id: "b", Tags: []}, {id: "c", Tags: [2]}]
+        }"#
+        .parse()
+        .unwrap()
+    }
+
+    fn query(path: &str) -> Vec<String> {
+        let chunk = chunk();
+        let tags = chunk.query(path).unwrap();
+        tags.into_iter().map(Tag::to_string).collect()
+    }
+
+    #[test]
+    fn select_tags() {
+        assert_eq!(
+            query(r#"Level.Sections[0].Palette[{Name:"minecraft:chest"}]"#),
+            [r#"{Name:"minecraft:chest",facing:"north"}"#]
+        );
+        assert_eq!(
+            query(r#"Level.Sections[].Palette[].facing"#),
+            [r#""north""#, r#""south""#]
+        );
+        assert_eq!(query("Level.Sections[-1].Y"), ["1b"]);
+        assert_eq!(query("Level.Sections[1].Y"), ["1b"]);
+        assert!(query("Level.Sections[-3]").is_empty());
+        assert!(query("Level.Sections[2]").is_empty());
+        assert_eq!(query(r#""a.b""#), ["2"]);
+        assert!(query("a").is_empty());
+
+        // Patterns
+        assert_eq!(query("{Version:1}.Version"), ["1"]);
+        assert!(query("{Version:2}.Version").is_empty());
+        assert_eq!(query("Level{Sections:[{Y:1b}]}.Sections[0].Y"), ["0b"]);
+        assert!(query("Level{Sections:[{Y:2b}]}").is_empty());
+    }
+
+    #[test]
+    fn list_patterns() {
+        // Each element of the pattern must be in the list, in any order
+        assert_eq!(query("Items[{Tags:[2]}].id"), [r#""a""#, r#""c""#]);
+        assert_eq!(query("Items[{Tags:[3,1]}].id"), [r#""a""#]);
+        assert!(query("Items[{Tags:[4]}].id").is_empty());
+        // But an empty list only matches empty lists
+        assert_eq!(query("Items[{Tags:[]}].id"), [r#""b""#]);
+    }
+
+    #[test]
+    fn invalid_paths() {
+        for path in ["", "a[", "a..b", "a[x]", "a.", "a b", "[0]]"] {
+            let err = path.parse::<NBTPath>().unwrap_err();
+            assert!(
+                matches!(err.kind, ErrorKind::InvalidPath(_)),
+                "{:?}: {}",
+                path,
+                err
+            );
+        }
+        assert!("a{b:".parse::<NBTPath>().is_err());
+    }
+}
@@ -0,0 +1,528 @@
+// Serde serializer which builds a tree of tags
+// The tree can then be saved with the `NBTWriter`
+
+use serde::ser::{self, Serialize};
+
+use super::{Compound, Error, Result, Tag};
+
+impl ser::Error for Error {
+    fn custom<T: std::fmt::Display>(msg: T) -> Error {
+        Error::custom(msg.to_string())
+    }
+}
+
+// Sequences are saved as lists, unless they are marked with one of these names
+// Use them with `#[serde(with = "nbt::ser::long_array")]` and similar
+pub(super) const BYTE_ARRAY: &str = "__nbt_byte_array";
+pub(super) const INT_ARRAY: &str = "__nbt_int_array";
+pub(super) const LONG_ARRAY: &str = "__nbt_long_array";
+
+pub fn to_tag<T: Serialize + ?Sized>(value: &T) -> Result<Tag> {
+    value
+        .serialize(Serializer)?
+        .ok_or_else(|| ser::Error::custom("the value has no content to save"))
+}
+
+pub mod byte_array {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(values: &[i8], s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_newtype_struct(super::BYTE_ARRAY, values)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<i8>, D::Error> {
+        Vec::deserialize(d)
+    }
+}
+
+pub mod int_array {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(values: &[i32], s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_newtype_struct(super::INT_ARRAY, values)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<i32>, D::Error> {
+        Vec::deserialize(d)
+    }
+}
+
+pub mod long_array {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(values: &[i64], s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_newtype_struct(super::LONG_ARRAY, values)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<i64>, D::Error> {
+        Vec::deserialize(d)
+    }
+}
+
+// There is no nbt tag for `None` and `()`, so they serialize to `None`
+// Struct and map fields with no value are then left out of the compound
+struct Serializer;
+
+// Other unsigned numbers are saved in the next bigger signed type, so that they always fit
+impl ser::Serializer for Serializer {
+    type Ok = Option<Tag>;
+    type Error = Error;
+
+    type SerializeSeq = SerializeList;
+    type SerializeTuple = SerializeList;
+    type SerializeTupleStruct = SerializeList;
+    type SerializeTupleVariant = SerializeVariant<SerializeList>;
+    type SerializeMap = SerializeCompound;
+    type SerializeStruct = SerializeCompound;
+    type SerializeStructVariant = SerializeVariant<SerializeCompound>;
+
+    fn serialize_bool(self, v: bool) -> Result<Option<Tag>> {
+        Ok(Some(Tag::Byte(v as i8)))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Option<Tag>> {
+        Ok(Some(Tag::Byte(v)))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Option<Tag>> {
+        Ok(Some(Tag::Short(v)))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Option<Tag>> {
+        Ok(Some(Tag::Int(v)))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Option<Tag>> {
+        Ok(Some(Tag::Long(v)))
+    }
+
+    // Bytes are often unsigned in rust, so u8 keeps its bits in a byte instead
+    fn serialize_u8(self, v: u8) -> Result<Option<Tag>> {
+        Ok(Some(Tag::Byte(v as i8)))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Option<Tag>> {
+        Ok(Some(Tag::Int(i32::from(v))))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Option<Tag>> {
+        Ok(Some(Tag::Long(i64::from(v))))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Option<Tag>> {
+        if v > i64::MAX as u64 {
+            return Err(ser::Error::custom("u64 is too big to be saved as a long"));
+        }
+        Ok(Some(Tag::Long(v as i64)))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Option<Tag>> {
+        Ok(Some(Tag::Float(v)))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Option<Tag>> {
+        Ok(Some(Tag::Double(v)))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Option<Tag>> {
+        Ok(Some(Tag::String(v.to_string())))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Option<Tag>> {
+        Ok(Some(Tag::String(v.to_owned())))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Option<Tag>> {
+        Ok(Some(Tag::ByteArray(v.iter().map(|b| *b as i8).collect())))
+    }
+
+    fn serialize_none(self) -> Result<Option<Tag>> {
+        Ok(None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Option<Tag>> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Option<Tag>> {
+        Ok(None)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Option<Tag>> {
+        Ok(None)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Option<Tag>> {
+        Ok(Some(Tag::String(variant.to_owned())))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<Option<Tag>> {
+        let tag = value.serialize(self)?;
+        match (name, tag) {
+            (BYTE_ARRAY, Some(Tag::List(tags))) => Ok(Some(Tag::ByteArray(
+                tags.iter()
+                    .map(|tag| tag.as_i8().copied())
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| ser::Error::custom("a byte array can only contain bytes"))?,
+            ))),
+            (INT_ARRAY, Some(Tag::List(tags))) => Ok(Some(Tag::IntArray(
+                tags.iter()
+                    .map(|tag| tag.as_i32().copied())
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| ser::Error::custom("an int array can only contain ints"))?,
+            ))),
+            (LONG_ARRAY, Some(Tag::List(tags))) => Ok(Some(Tag::LongArray(
+                tags.iter()
+                    .map(|tag| tag.as_i64().copied())
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| ser::Error::custom("a long array can only contain longs"))?,
+            ))),
+            (_, tag) => Ok(tag),
+        }
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Option<Tag>> {
+        let mut comp = Compound::new();
+        if let Some(tag) = value.serialize(self).map_err(|err| err.in_key(variant))? {
+            comp.insert(variant.to_owned(), tag);
+        }
+        Ok(Some(Tag::Compound(comp)))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeList> {
+        Ok(SerializeList {
+            tags: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeList> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SerializeList> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVariant<SerializeList>> {
+        Ok(SerializeVariant {
+            variant,
+            inner: self.serialize_seq(Some(len))?,
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeCompound> {
+        Ok(SerializeCompound {
+            comp: Compound::new(),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeCompound> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVariant<SerializeCompound>> {
+        Ok(SerializeVariant {
+            variant,
+            inner: self.serialize_map(Some(len))?,
+        })
+    }
+}
+
+struct SerializeList {
+    tags: Vec<Tag>,
+}
+
+impl SerializeList {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let index = self.tags.len() as i32;
+        let tag = value
+            .serialize(Serializer)
+            .and_then(|tag| {
+                let tag =
+                    tag.ok_or_else(|| ser::Error::custom("lists can't contain empty values"))?;
+                if self
+                    .tags
+                    .first()
+                    .is_some_and(|first| first.id() != tag.id())
+                {
+                    return Err(ser::Error::custom(
+                        "all the tags in a list must have the same type",
+                    ));
+                }
+                Ok(tag)
+            })
+            .map_err(|err: Error| err.in_index(index))?;
+        self.tags.push(tag);
+        Ok(())
+    }
+}
+
+impl ser::SerializeSeq for SerializeList {
+    type Ok = Option<Tag>;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Option<Tag>> {
+        Ok(Some(Tag::List(self.tags)))
+    }
+}
+
+impl ser::SerializeTuple for SerializeList {
+    type Ok = Option<Tag>;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Option<Tag>> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeList {
+    type Ok = Option<Tag>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Option<Tag>> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+struct SerializeCompound {
+    comp: Compound,
+    // The key of the value which is about to be serialized
+    key: Option<String>,
+}
+
+impl SerializeCompound {
+    fn insert<T: Serialize + ?Sized>(&mut self, key: String, value: &T) -> Result<()> {
+        if let Some(tag) = value
+            .serialize(Serializer)
+            .map_err(|err| err.in_key(&key))?
+        {
+            self.comp.insert(key, tag);
+        }
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for SerializeCompound {
+    type Ok = Option<Tag>;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        // The keys of a compound can only be strings
+        match key.serialize(Serializer)? {
+            Some(Tag::String(key)) => {
+                self.key = Some(key);
+                Ok(())
+            }
+            _ => Err(ser::Error::custom("compound keys must be strings")),
+        }
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let key = self
+            .key
+            .take()
+            .expect("serialize_value called before serialize_key");
+        self.insert(key, value)
+    }
+
+    fn end(self) -> Result<Option<Tag>> {
+        Ok(Some(Tag::Compound(self.comp)))
+    }
+}
+
+impl ser::SerializeStruct for SerializeCompound {
+    type Ok = Option<Tag>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.insert(key.to_owned(), value)
+    }
+
+    fn end(self) -> Result<Option<Tag>> {
+        ser::SerializeMap::end(self)
+    }
+}
+
+// Enum variants with content are saved as a compound with a single key
+struct SerializeVariant<S> {
+    variant: &'static str,
+    inner: S,
+}
+
+impl<S> SerializeVariant<S> {
+    fn wrap(variant: &'static str, tag: Option<Tag>) -> Result<Option<Tag>> {
+        let mut comp = Compound::new();
+        if let Some(tag) = tag {
+            comp.insert(variant.to_owned(), tag);
+        }
+        Ok(Some(Tag::Compound(comp)))
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeVariant<SerializeList> {
+    type Ok = Option<Tag>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let variant = self.variant;
+        self.inner.push(value).map_err(|err| err.in_key(variant))
+    }
+
+    fn end(self) -> Result<Option<Tag>> {
+        let tag = ser::SerializeSeq::end(self.inner)?;
+        Self::wrap(self.variant, tag)
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeVariant<SerializeCompound> {
+    type Ok = Option<Tag>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        let variant = self.variant;
+        self.inner
+            .insert(key.to_owned(), value)
+            .map_err(|err| err.in_key(variant))
+    }
+
+    fn end(self) -> Result<Option<Tag>> {
+        let tag = ser::SerializeMap::end(self.inner)?;
+        Self::wrap(self.variant, tag)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::de::from_tag;
+    use super::*;
+    use serde::{Deserialize, Serialize};
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Shape {
+        Empty,
+        Point(i32, i32),
+        Box { min: i32, max: i32 },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct Everything {
+        flag: bool,
+        byte: i8,
+        unsigned_byte: u8,
+        short: i16,
+        unsigned_short: u16,
+        int: i32,
+        unsigned_int: u32,
+        long: i64,
+        unsigned_long: u64,
+        float: f32,
+        double: f64,
+        name: String,
+        missing: Option<i32>,
+        present: Option<String>,
+        list: Vec<Vec<i16>>,
+        #[serde(with = "byte_array")]
+        bytes: Vec<i8>,
+        #[serde(with = "int_array")]
+        ints: Vec<i32>,
+        #[serde(with = "long_array")]
+        longs: Vec<i64>,
+        map: BTreeMap<String, u8>,
+        // Unit variants are strings, so they can't be in a list with the other variants
+        empty: Shape,
+        shapes: Vec<Shape>,
+    }
+
+    #[test]
+    fn round_trip() {
+        let value = Everything {
+            flag: true,
+            byte: -5,
+            unsigned_byte: 200,
+            short: -300,
+            unsigned_short: u16::MAX,
+            int: 70_000,
+            unsigned_int: u32::MAX,
+            long: i64::MIN,
+            unsigned_long: i64::MAX as u64,
+            float: 1.5,
+            double: -0.1,
+            name: "minecraft:stone".to_owned(),
+            missing: None,
+            present: Some("here".to_owned()),
+            list: vec![vec![1, 2], vec![]],
+            bytes: vec![1, -1],
+            ints: vec![3],
+            longs: vec![],
+            map: vec![("a".to_owned(), 255), ("b".to_owned(), 0)]
+                .into_iter()
+                .collect(),
+            empty: Shape::Empty,
+            shapes: vec![Shape::Point(1, -1), Shape::Box { min: 0, max: 16 }],
+        };
+
+        let tag = to_tag(&value).unwrap();
+        let comp = tag.as_compound().unwrap();
+        assert_eq!(comp["UnsignedByte"], Tag::Byte(-56));
+        assert_eq!(comp["UnsignedShort"], Tag::Int(65535));
+        assert_eq!(comp["Ints"], Tag::IntArray(vec![3]));
+        assert!(!comp.contains_key("Missing"));
+        assert_eq!(from_tag::<Everything>(&tag).unwrap(), value);
+    }
+
+    #[test]
+    fn too_big_numbers_are_errors() {
+        assert!(to_tag(&u64::MAX).is_err());
+        assert!(to_tag(&()).is_err());
+    }
+}
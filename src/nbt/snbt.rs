@@ -0,0 +1,511 @@
+// The stringified nbt format (SNBT), used by minecraft commands like `/data get`
+// Ex: {Name:"minecraft:stone",Count:1b,Pos:[I;1,2,3]}
+
+use std::fmt;
+use std::fmt::Write;
+use std::str::FromStr;
+
+use super::{Compound, Error, ErrorKind, Result, Tag};
+
+// Characters which can be used in keys and strings without quotes
+fn is_unquoted(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.' || c == '+'
+}
+
+fn write_string(f: &mut fmt::Formatter, string: &str) -> fmt::Result {
+    // Like minecraft, use single quotes only when they avoid escaping
+    let quote = if string.contains('"') && !string.contains('\'') {
+        '\''
+    } else {
+        '"'
+    };
+
+    f.write_char(quote)?;
+    for c in string.chars() {
+        if c == quote || c == '\\' {
+            f.write_char('\\')?;
+        }
+        f.write_char(c)?;
+    }
+    f.write_char(quote)
+}
+
+fn write_key(f: &mut fmt::Formatter, key: &str) -> fmt::Result {
+    if !key.is_empty() && key.chars().all(is_unquoted) {
+        f.write_str(key)
+    } else {
+        write_string(f, key)
+    }
+}
+
+fn write_indent(f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+    for _ in 0..depth {
+        f.write_str("    ")?;
+    }
+    Ok(())
+}
+
+// Writes the elements of a list, array or compound between `open` and `close`
+// When `pretty` every element is written on its own line
+fn write_elements<T>(
+    f: &mut fmt::Formatter,
+    depth: usize,
+    pretty: bool,
+    open: &str,
+    close: &str,
+    elements: impl ExactSizeIterator<Item = T>,
+    mut write_element: impl FnMut(&mut fmt::Formatter, T) -> fmt::Result,
+) -> fmt::Result {
+    f.write_str(open)?;
+    let pretty = pretty && elements.len() > 0;
+    for (i, element) in elements.enumerate() {
+        if i != 0 {
+            f.write_char(',')?;
+        }
+        if pretty {
+            f.write_char('\n')?;
+            write_indent(f, depth + 1)?;
+        }
+        write_element(f, element)?;
+    }
+    if pretty {
+        f.write_char('\n')?;
+        write_indent(f, depth)?;
+    }
+    f.write_str(close)
+}
+
+fn write_tag(f: &mut fmt::Formatter, tag: &Tag, depth: usize) -> fmt::Result {
+    // With `{:#}` lists and compounds are indented
+    // But arrays are kept on a single line, as they are often very long
+    let pretty = f.alternate();
+    match tag {
+        Tag::Byte(n) => write!(f, "{}b", n),
+        Tag::Short(n) => write!(f, "{}s", n),
+        Tag::Int(n) => write!(f, "{}", n),
+        Tag::Long(n) => write!(f, "{}L", n),
+        Tag::Float(n) => write_decimal(f, f64::from(*n), &n.to_string(), "1e39", 'f'),
+        Tag::Double(n) => write_decimal(f, *n, &n.to_string(), "1e309", 'd'),
+        Tag::String(string) => write_string(f, string),
+        Tag::ByteArray(bytes) => {
+            write_elements(f, depth, false, "[B;", "]", bytes.iter(), |f, n| {
+                write!(f, "{}b", n)
+            })
+        }
+        Tag::IntArray(ints) => write_elements(f, depth, false, "[I;", "]", ints.iter(), |f, n| {
+            write!(f, "{}", n)
+        }),
+        Tag::LongArray(longs) => {
+            write_elements(f, depth, false, "[L;", "]", longs.iter(), |f, n| {
+                write!(f, "{}L", n)
+            })
+        }
+        Tag::List(tags) => write_elements(f, depth, pretty, "[", "]", tags.iter(), |f, tag| {
+            write_tag(f, tag, depth + 1)
+        }),
+        Tag::Compound(comp) => write_compound(f, comp, depth),
+    }
+}
+
+// Infinities are written as numbers too big for the type, which are read back as infinities
+// Minecraft can't write NaN, so NaN with the suffix is read as a special case
+fn write_decimal(
+    f: &mut fmt::Formatter,
+    value: f64,
+    text: &str,
+    too_big: &str,
+    suffix: char,
+) -> fmt::Result {
+    if value.is_nan() {
+        write!(f, "NaN{}", suffix)
+    } else if value.is_infinite() {
+        let sign = if value < 0.0 { "-" } else { "" };
+        write!(f, "{}{}{}", sign, too_big, suffix)
+    } else {
+        write!(f, "{}{}", text, suffix)
+    }
+}
+
+fn write_compound(f: &mut fmt::Formatter, comp: &Compound, depth: usize) -> fmt::Result {
+    let pretty = f.alternate();
+    let separator = if pretty { ": " } else { ":" };
+    write_elements(f, depth, pretty, "{", "}", comp.iter(), |f, (key, tag)| {
+        write_key(f, key)?;
+        f.write_str(separator)?;
+        write_tag(f, tag, depth + 1)
+    })
+}
+
+// `{}` prints the compact format, `{:#}` an indented one
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_tag(f, self, 0)
+    }
+}
+
+impl FromStr for Tag {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Tag> {
+        let mut parser = SNBTParser::new(text);
+        let tag = parser.read_tag()?;
+        parser.skip_whitespace();
+        if parser.peek().is_some() {
+            return Err(parser.error("trailing characters after the tag"));
+        }
+        Ok(tag)
+    }
+}
+
+pub struct SNBTParser<'a> {
+    text: &'a str,
+    // Position in bytes in `text`
+    position: usize,
+}
+
+impl<'a> SNBTParser<'a> {
+    pub fn new(text: &'a str) -> SNBTParser<'a> {
+        SNBTParser { text, position: 0 }
+    }
+
+    pub(super) fn position(&self) -> usize {
+        self.position
+    }
+
+    fn error(&self, message: &'static str) -> Error {
+        Error::new(ErrorKind::InvalidSnbt(message), self.position as u64)
+    }
+
+    pub(super) fn peek(&self) -> Option<char> {
+        self.text[self.position..].chars().next()
+    }
+
+    pub(super) fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char, message: &'static str) -> Result<()> {
+        self.skip_whitespace();
+        if self.peek() == Some(expected) {
+            self.next();
+            Ok(())
+        } else {
+            Err(self.error(message))
+        }
+    }
+
+    // Consumes the separator between two elements
+    // Returns false if the list of elements is over
+    fn read_separator(&mut self) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(',') {
+            self.next();
+            self.skip_whitespace();
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn read_tag(&mut self) -> Result<Tag> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => self.read_compound(),
+            Some('[') => self.read_list_or_array(),
+            Some('"') | Some('\'') => Ok(Tag::String(self.read_quoted()?)),
+            Some(_) => {
+                let start = self.position;
+                let literal = self.read_unquoted();
+                if literal.is_empty() {
+                    self.position = start;
+                    Err(self.error("expected a value"))
+                } else {
+                    Ok(parse_literal(literal))
+                }
+            }
+            None => Err(self.error("expected a value")),
+        }
+    }
+
+    fn read_unquoted(&mut self) -> &'a str {
+        let start = self.position;
+        while let Some(c) = self.peek() {
+            if !is_unquoted(c) {
+                break;
+            }
+            self.next();
+        }
+        &self.text[start..self.position]
+    }
+
+    pub(super) fn read_quoted(&mut self) -> Result<String> {
+        let quote = self.next().unwrap();
+        let mut string = String::new();
+        loop {
+            match self.next() {
+                Some('\\') => {
+                    let c = match self.next() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some(c) if c == '\\' || c == '"' || c == '\'' => c,
+                        _ => return Err(self.error("invalid escape sequence")),
+                    };
+                    string.push(c);
+                }
+                Some(c) if c == quote => return Ok(string),
+                Some(c) => string.push(c),
+                None => return Err(self.error("unclosed string")),
+            }
+        }
+    }
+
+    fn read_key(&mut self) -> Result<String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('"') | Some('\'') => self.read_quoted(),
+            _ => {
+                let key = self.read_unquoted();
+                if key.is_empty() {
+                    Err(self.error("expected a key"))
+                } else {
+                    Ok(key.to_owned())
+                }
+            }
+        }
+    }
+
+    pub(super) fn read_compound(&mut self) -> Result<Tag> {
+        self.expect('{', "expected '{'")?;
+        self.skip_whitespace();
+
+        let mut comp = Compound::new();
+        while self.peek() != Some('}') {
+            let key = self.read_key()?;
+            self.expect(':', "expected ':' after the key")?;
+            let tag = self.read_tag().map_err(|err| err.in_key(&key))?;
+            comp.insert(key, tag);
+
+            if !self.read_separator() {
+                break;
+            }
+        }
+
+        self.expect('}', "expected ',' or '}'")?;
+        Ok(Tag::Compound(comp))
+    }
+
+    fn read_list_or_array(&mut self) -> Result<Tag> {
+        self.expect('[', "expected '['")?;
+
+        // Arrays start with their type, ex: [I;1,2,3]
+        let rest = &self.text[self.position..];
+        let mut chars = rest.chars();
+        if let (Some(kind), Some(';')) = (chars.next(), chars.next()) {
+            if kind == 'B' || kind == 'I' || kind == 'L' {
+                self.position += 2;
+                return self.read_array(kind);
+            }
+        }
+
+        self.skip_whitespace();
+        let mut tags: Vec<Tag> = Vec::new();
+        while self.peek() != Some(']') {
+            let start = self.position;
+            let tag = self
+                .read_tag()
+                .map_err(|err| err.in_index(tags.len() as i32))?;
+            if tags.first().is_some_and(|first| first.id() != tag.id()) {
+                self.position = start;
+                return Err(self.error("all the tags in a list must have the same type"));
+            }
+            tags.push(tag);
+
+            if !self.read_separator() {
+                break;
+            }
+        }
+
+        self.expect(']', "expected ',' or ']'")?;
+        Ok(Tag::List(tags))
+    }
+
+    fn read_array(&mut self, kind: char) -> Result<Tag> {
+        let (min, max) = match kind {
+            'B' => (i64::from(i8::MIN), i64::from(i8::MAX)),
+            'I' => (i64::from(i32::MIN), i64::from(i32::MAX)),
+            _ => (i64::MIN, i64::MAX),
+        };
+
+        self.skip_whitespace();
+        let mut numbers = Vec::new();
+        while self.peek() != Some(']') {
+            let start = self.position;
+            let number = match parse_literal(self.read_unquoted()) {
+                Tag::Byte(n) => i64::from(n),
+                Tag::Short(n) => i64::from(n),
+                Tag::Int(n) => i64::from(n),
+                Tag::Long(n) => n,
+                _ => {
+                    self.position = start;
+                    return Err(self.error("expected an integer in the array"));
+                }
+            };
+            if number < min || number > max {
+                self.position = start;
+                return Err(self.error("number too big for the array type"));
+            }
+            numbers.push(number);
+
+            if !self.read_separator() {
+                break;
+            }
+        }
+        self.expect(']', "expected ',' or ']'")?;
+
+        // All the numbers were checked to fit in the type of the array
+        Ok(match kind {
+            'B' => Tag::ByteArray(numbers.into_iter().map(|n| n as i8).collect()),
+            'I' => Tag::IntArray(numbers.into_iter().map(|n| n as i32).collect()),
+            _ => Tag::LongArray(numbers),
+        })
+    }
+}
+
+// Unquoted values are numbers when they look like numbers, otherwise strings
+// Numbers which don't fit in their type are also kept as strings, like minecraft does
+fn parse_literal(literal: &str) -> Tag {
+    match literal {
+        "true" => return Tag::Byte(1),
+        "false" => return Tag::Byte(0),
+        _ => {}
+    }
+
+    let (number, suffix) = match literal.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&literal[..i], Some(c.to_ascii_lowercase())),
+        _ => (literal, None),
+    };
+
+    let tag = match suffix {
+        Some('b') if is_integer(number) => number.parse().ok().map(Tag::Byte),
+        Some('s') if is_integer(number) => number.parse().ok().map(Tag::Short),
+        Some('l') if is_integer(number) => number.parse().ok().map(Tag::Long),
+        Some('f') if number == "NaN" => Some(Tag::Float(f32::NAN)),
+        Some('d') if number == "NaN" => Some(Tag::Double(f64::NAN)),
+        Some('f') if is_decimal(number, false) => number.parse().ok().map(Tag::Float),
+        Some('d') if is_decimal(number, false) => number.parse().ok().map(Tag::Double),
+        None if is_integer(number) => number.parse().ok().map(Tag::Int),
+        // Without a suffix, decimals need a '.' or an exponent
+        None if is_decimal(number, true) => number.parse().ok().map(Tag::Double),
+        _ => None,
+    };
+
+    tag.unwrap_or_else(|| Tag::String(literal.to_owned()))
+}
+
+// [-+]?(0|[1-9][0-9]*)
+fn is_integer(number: &str) -> bool {
+    let digits = number.trim_start_matches(['-', '+']);
+    number.len() - digits.len() <= 1
+        && !digits.is_empty()
+        && digits.chars().all(|c| c.is_ascii_digit())
+        && (digits == "0" || !digits.starts_with('0'))
+}
+
+// [-+]?([0-9]+[.]?|[0-9]*[.][0-9]+)(e[-+]?[0-9]+)?
+fn is_decimal(number: &str, needs_point: bool) -> bool {
+    let unsigned = number.trim_start_matches(['-', '+']);
+    if number.len() - unsigned.len() > 1 {
+        return false;
+    }
+
+    let (mantissa, exponent) = match unsigned.find(['e', 'E']) {
+        Some(i) => (&unsigned[..i], Some(&unsigned[i + 1..])),
+        None => (unsigned, None),
+    };
+
+    let (integer, fraction) = match mantissa.find('.') {
+        Some(i) => (&mantissa[..i], Some(&mantissa[i + 1..])),
+        None => (mantissa, None),
+    };
+
+    let all_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    let valid_mantissa = all_digits(integer)
+        && fraction.is_none_or(all_digits)
+        && !(integer.is_empty() && fraction.is_none_or(str::is_empty));
+    let valid_exponent = exponent.is_none_or(|exponent| {
+        let digits = exponent.trim_start_matches(['-', '+']);
+        exponent.len() - digits.len() <= 1 && !digits.is_empty() && all_digits(digits)
+    });
+
+    valid_mantissa && valid_exponent && (!needs_point || fraction.is_some() || exponent.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(tag: &Tag) {
+        assert_eq!(&tag.to_string().parse::<Tag>().unwrap(), tag);
+        assert_eq!(&format!("{:#}", tag).parse::<Tag>().unwrap(), tag);
+    }
+
+    #[test]
+    fn every_tag() {
+        let text = r#"{byte:-3b,short:300s,int:7,long:-9000000000L,float:0.5f,double:1e-7d,
+            string:"with \"quotes\" and 'apostrophes'",'quoted key':abc,
+            bytes:[B;1b,-128b],ints:[I;],longs:[L;1L,2L],
+            list:[[1,2],[],["a"]],compounds:[{},{a:{b:1b}}]}"#;
+        let tag: Tag = text.parse().unwrap();
+        let comp = tag.as_compound().unwrap();
+        assert_eq!(comp["short"], Tag::Short(300));
+        assert_eq!(comp["double"], Tag::Double(1e-7));
+        assert_eq!(comp["quoted key"], Tag::String("abc".to_owned()));
+        assert_eq!(comp["ints"], Tag::IntArray(vec![]));
+        round_trip(&tag);
+    }
+
+    #[test]
+    fn decimals() {
+        for n in [0.0, -0.0, 0.1, 1e30, -3.5e-20, f32::MAX, f32::MIN_POSITIVE] {
+            round_trip(&Tag::Float(n));
+        }
+        for n in [0.1, 1e300, -2.5e-300, f64::MAX, f64::MIN_POSITIVE] {
+            round_trip(&Tag::Double(n));
+        }
+    }
+
+    #[test]
+    fn non_finite_decimals() {
+        for n in [f32::INFINITY, f32::NEG_INFINITY] {
+            round_trip(&Tag::Float(n));
+        }
+        for n in [f64::INFINITY, f64::NEG_INFINITY] {
+            round_trip(&Tag::Double(n));
+        }
+        assert_eq!(Tag::Float(f32::INFINITY).to_string(), "1e39f");
+
+        // NaN isn't equal to itself
+        match Tag::Float(f32::NAN).to_string().parse() {
+            Ok(Tag::Float(n)) => assert!(n.is_nan()),
+            tag => panic!("expected a float, got {:?}", tag),
+        }
+        match Tag::Double(f64::NAN).to_string().parse() {
+            Ok(Tag::Double(n)) => assert!(n.is_nan()),
+            tag => panic!("expected a double, got {:?}", tag),
+        }
+        // Without a suffix it is still a string
+        assert_eq!("NaN".parse::<Tag>().unwrap(), Tag::String("NaN".to_owned()));
+    }
+}
@@ -0,0 +1,237 @@
+use std::io;
+use std::io::prelude::*;
+
+use byteorder::{BigEndian, WriteBytesExt};
+use flate2::write::{GzEncoder, ZlibEncoder};
+
+use super::mutf8;
+use super::{Compound, Tag};
+
+// How the written nbt data is wrapped
+// level.dat and player files use gzip, region files usually use zlib
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zlib,
+}
+
+pub struct NBTWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> NBTWriter<W> {
+    pub fn new(writer: W) -> NBTWriter<W> {
+        NBTWriter { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    // Every file starts with a named compound, which usually has an empty name
+    pub fn write_root(&mut self, name: &str, comp: &Compound) -> io::Result<()> {
+        self.writer.write_u8(10)?;
+        self.write_mutf8(name)?;
+        self.write_compound(comp)
+    }
+
+    // Writes the payload of a tag, without its id and name
+    pub fn write_tag(&mut self, tag: &Tag) -> io::Result<()> {
+        match tag {
+            Tag::Byte(n) => self.writer.write_i8(*n),
+            Tag::Short(n) => self.writer.write_i16::<BigEndian>(*n),
+            Tag::Int(n) => self.writer.write_i32::<BigEndian>(*n),
+            Tag::Long(n) => self.writer.write_i64::<BigEndian>(*n),
+            Tag::Float(n) => self.writer.write_f32::<BigEndian>(*n),
+            Tag::Double(n) => self.writer.write_f64::<BigEndian>(*n),
+            Tag::ByteArray(bytes) => {
+                self.write_length(bytes.len())?;
+                for byte in bytes {
+                    self.writer.write_i8(*byte)?;
+                }
+                Ok(())
+            }
+            Tag::String(string) => self.write_mutf8(string),
+            Tag::List(tags) => self.write_list(tags),
+            Tag::Compound(comp) => self.write_compound(comp),
+            Tag::IntArray(ints) => {
+                self.write_length(ints.len())?;
+                for int in ints {
+                    self.writer.write_i32::<BigEndian>(*int)?;
+                }
+                Ok(())
+            }
+            Tag::LongArray(longs) => {
+                self.write_length(longs.len())?;
+                for long in longs {
+                    self.writer.write_i64::<BigEndian>(*long)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn write_compound(&mut self, comp: &Compound) -> io::Result<()> {
+        for (name, tag) in comp {
+            self.writer.write_u8(tag.id())?;
+            self.write_mutf8(name)?;
+            self.write_tag(tag)?;
+        }
+        // The end of the compound
+        self.writer.write_u8(0)
+    }
+
+    fn write_list(&mut self, tags: &[Tag]) -> io::Result<()> {
+        // Empty lists are saved with the End tag as their type
+        let id = tags.first().map_or(0, Tag::id);
+        if tags.iter().any(|tag| tag.id() != id) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "All the tags in a list must have the same type",
+            ));
+        }
+
+        self.writer.write_u8(id)?;
+        self.write_length(tags.len())?;
+        for tag in tags {
+            self.write_tag(tag)?;
+        }
+        Ok(())
+    }
+
+    fn write_length(&mut self, length: usize) -> io::Result<()> {
+        if length > i32::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Array is too long to be saved",
+            ));
+        }
+        self.writer.write_i32::<BigEndian>(length as i32)
+    }
+
+    fn write_mutf8(&mut self, string: &str) -> io::Result<()> {
+        let bytes = mutf8::encode(string);
+        if bytes.len() > usize::from(u16::MAX) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "String is too long to be saved",
+            ));
+        }
+        self.writer.write_u16::<BigEndian>(bytes.len() as u16)?;
+        self.writer.write_all(&bytes)
+    }
+}
+
+// Serializes a whole file, ready to be saved on disk or in a region file
+pub fn to_bytes(name: &str, comp: &Compound, compression: Compression) -> io::Result<Vec<u8>> {
+    match compression {
+        Compression::None => {
+            let mut writer = NBTWriter::new(Vec::new());
+            writer.write_root(name, comp)?;
+            Ok(writer.into_inner())
+        }
+        Compression::Gzip => {
+            let mut writer = NBTWriter::new(GzEncoder::new(Vec::new(), Default::default()));
+            writer.write_root(name, comp)?;
+            writer.into_inner().finish()
+        }
+        Compression::Zlib => {
+            let mut writer = NBTWriter::new(ZlibEncoder::new(Vec::new(), Default::default()));
+            writer.write_root(name, comp)?;
+            writer.into_inner().finish()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::NBTParser;
+    use super::*;
+    use flate2::read::{GzDecoder, ZlibDecoder};
+
+    // A compound with every type of tag, including nested and empty lists
+    fn every_tag() -> Compound {
+        let mut inner = Compound::new();
+        inner.insert("name".to_owned(), Tag::String("minecraft:stone".to_owned()));
+        inner.insert("empty".to_owned(), Tag::Compound(Compound::new()));
+
+        let mut comp = Compound::new();
+        comp.insert("byte".to_owned(), Tag::Byte(-128));
+        comp.insert("short".to_owned(), Tag::Short(i16::MAX));
+        comp.insert("int".to_owned(), Tag::Int(-123_456));
+        comp.insert("long".to_owned(), Tag::Long(i64::MIN));
+        comp.insert("float".to_owned(), Tag::Float(0.25));
+        comp.insert("double".to_owned(), Tag::Double(-1e300));
+        comp.insert("bytes".to_owned(), Tag::ByteArray(vec![0, -1, 127]));
+        comp.insert(
+            "string".to_owned(),
+            Tag::String("nul \0 and \u{1F600}".to_owned()),
+        );
+        comp.insert(
+            "ints".to_owned(),
+            Tag::IntArray(vec![i32::MIN, 0, i32::MAX]),
+        );
+        comp.insert("longs".to_owned(), Tag::LongArray(vec![]));
+        comp.insert("empty list".to_owned(), Tag::List(vec![]));
+        comp.insert(
+            "nested lists".to_owned(),
+            Tag::List(vec![
+                Tag::List(vec![Tag::Int(1), Tag::Int(2)]),
+                Tag::List(vec![]),
+                Tag::List(vec![Tag::String("a".to_owned())]),
+            ]),
+        );
+        comp.insert(
+            "compounds".to_owned(),
+            Tag::List(vec![
+                Tag::Compound(inner.clone()),
+                Tag::Compound(Compound::new()),
+            ]),
+        );
+        comp.insert("compound".to_owned(), Tag::Compound(inner));
+        comp
+    }
+
+    fn read_back(bytes: &[u8]) -> Tag {
+        let tag = NBTParser::new(bytes).read_compound().unwrap();
+        let mut root = match Tag::from(tag) {
+            Tag::Compound(root) => root,
+            tag => panic!("expected a compound, got {:?}", tag),
+        };
+        root.swap_remove("level").unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let comp = every_tag();
+        for compression in [Compression::None, Compression::Gzip, Compression::Zlib] {
+            let bytes = to_bytes("level", &comp, compression).unwrap();
+            let mut uncompressed = Vec::new();
+            match compression {
+                Compression::None => uncompressed = bytes,
+                Compression::Gzip => {
+                    GzDecoder::new(&bytes[..])
+                        .read_to_end(&mut uncompressed)
+                        .unwrap();
+                }
+                Compression::Zlib => {
+                    ZlibDecoder::new(&bytes[..])
+                        .read_to_end(&mut uncompressed)
+                        .unwrap();
+                }
+            }
+            assert_eq!(read_back(&uncompressed), Tag::Compound(comp.clone()));
+        }
+    }
+
+    #[test]
+    fn mixed_lists_are_errors() {
+        let mut comp = Compound::new();
+        comp.insert(
+            "list".to_owned(),
+            Tag::List(vec![Tag::Int(1), Tag::Byte(1)]),
+        );
+        assert!(to_bytes("", &comp, Compression::None).is_err());
+    }
+}
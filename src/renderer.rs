@@ -1,38 +1,185 @@
 use image::ImageBuffer;
 use std::collections::HashSet;
 
+use super::biome;
 use super::loader::TextureLoader;
 use super::map::Region;
 use std::sync::RwLock;
 
-fn overlay(bottom: &mut image::RgbaImage, top: &image::RgbaImage, x: u32, y: u32) {
+// The top image is multiplied by the tint, if there is one
+fn overlay(
+    bottom: &mut image::RgbaImage,
+    top: &image::RgbaImage,
+    x: u32,
+    y: u32,
+    tint: Option<[u8; 3]>,
+) {
     for dx in 0..top.width() {
         for dy in 0..top.height() {
-            let top_pixel = top.get_pixel(dx, dy);
+            let mut top_pixel = *top.get_pixel(dx, dy);
             let bottom_pixel = bottom.get_pixel(x + dx, y + dy);
 
             // Only paint if this pixel is invisible
             if bottom_pixel[3] == 0 {
-                bottom.put_pixel(x + dx, y + dy, *top_pixel);
+                if let Some(tint) = tint {
+                    let [r, g, b] =
+                        biome::tint_color([top_pixel[0], top_pixel[1], top_pixel[2]], tint);
+                    top_pixel = image::Rgba([r, g, b, top_pixel[3]]);
+                }
+                bottom.put_pixel(x + dx, y + dy, top_pixel);
             }
         }
     }
 }
 
-fn tint_height(avg: &mut [u8; 3], height: u8) {
+// Multiplies the color by shade / 255, so it gets darker below 255 and lighter above
+fn tint_height(avg: &mut [u8; 3], shade: u32) {
     for x in avg {
-        *x = u32::min(u32::from(*x) * u32::from(height) / 255, 255) as u8;
+        *x = u32::min(u32::from(*x) * shade / 255, 255) as u8;
+    }
+}
+
+// The height around which the shading doesn't change the colors
+const SEA_LEVEL: i32 = 63;
+
+// The side of a region in blocks
+const REGION_BLOCKS: usize = 16 * 32;
+
+// The height of every column of a region, and of the columns just outside its north and west sides
+// So that the shading continues across the borders between regions
+pub struct Heights {
+    columns: Vec<Option<i32>>,
+    north: Vec<Option<i32>>,
+    west: Vec<Option<i32>>,
+}
+
+impl Heights {
+    // north and west are the regions next to this one, they only need the chunks on its border
+    pub fn new(
+        region: &Region,
+        north: Option<&Region>,
+        west: Option<&Region>,
+        ignore: &HashSet<String>,
+        roof: Option<i32>,
+    ) -> Heights {
+        let last = REGION_BLOCKS - 1;
+        let range = region.height_range();
+        let columns = (0..REGION_BLOCKS * REGION_BLOCKS)
+            .map(|i| {
+                let (x, z) = (i % REGION_BLOCKS, i / REGION_BLOCKS);
+                column_height(region, range, ignore, x, z, roof)
+            })
+            .collect();
+        let north = match north {
+            Some(north) => {
+                let range = north.height_range();
+                (0..REGION_BLOCKS)
+                    .map(|x| column_height(north, range, ignore, x, last, roof))
+                    .collect()
+            }
+            None => vec![None; REGION_BLOCKS],
+        };
+        let west = match west {
+            Some(west) => {
+                let range = west.height_range();
+                (0..REGION_BLOCKS)
+                    .map(|z| column_height(west, range, ignore, last, z, roof))
+                    .collect()
+            }
+            None => vec![None; REGION_BLOCKS],
+        };
+
+        Heights {
+            columns,
+            north,
+            west,
+        }
     }
+
+    // x and z can be -1, for the columns of the regions to the west and to the north
+    fn get(&self, x: i32, z: i32) -> Option<i32> {
+        match (x, z) {
+            (-1, -1) => None,
+            (-1, z) => self.west[z as usize],
+            (x, -1) => self.north[x as usize],
+            (x, z) => self.columns[z as usize * REGION_BLOCKS + x as usize],
+        }
+    }
+
+    // Like a hillshade lit from the north-west, slopes facing it are lighter and the others darker
+    // Higher columns are also a little lighter, so plains on different levels can be told apart
+    fn shade(&self, x: usize, z: usize, height: i32) -> u32 {
+        let (x, z) = (x as i32, z as i32);
+        let slope: i32 = [self.get(x, z - 1), self.get(x - 1, z)]
+            .iter()
+            .map(|neighbor| neighbor.map_or(0, |neighbor| height - neighbor))
+            .sum();
+        let relief = (slope * 12).clamp(-64, 64);
+        let altitude = ((height - SEA_LEVEL) / 4).clamp(-32, 32);
+        (224 + relief + altitude) as u32
+    }
+}
+
+// The height of the highest block of the column which isn't ignored, None if there are none
+// range is the height range of the region, which is computed once for all its columns
+fn column_height(
+    region: &Region,
+    range: (i32, i32),
+    ignore: &HashSet<String>,
+    x: usize,
+    z: usize,
+    roof: Option<i32>,
+) -> Option<i32> {
+    let (min_y, _) = range;
+    let start_y = start_height(region, range, ignore, x, z, roof);
+    (min_y..=start_y)
+        .rev()
+        .find(|y| !ignore.contains(region.get_block(x, *y, z)))
 }
 
+// The height from which the column is rendered
+// With a roof, the blocks are skipped until the first ignored one (ex: air) below it
+// Otherwise the heightmap of the chunk gives the highest block, if the chunk has one
+fn start_height(
+    region: &Region,
+    (min_y, max_y): (i32, i32),
+    ignore: &HashSet<String>,
+    x: usize,
+    z: usize,
+    roof: Option<i32>,
+) -> i32 {
+    match roof {
+        Some(roof) => (min_y..=i32::min(roof, max_y - 1))
+            .rev()
+            .find(|y| ignore.contains(region.get_block(x, *y, z)))
+            .unwrap_or(min_y - 1),
+        None => region
+            .get_height(x, z)
+            .map_or(max_y - 1, |height| height - 1),
+    }
+}
+
+// The columns are shaded by their height if `heights` is given
 pub fn image_chunk(
     region: &Region,
     ignore: &HashSet<String>,
     textures: &RwLock<TextureLoader>,
+    roof: Option<i32>,
+    heights: Option<&Heights>,
 ) -> image::RgbImage {
+    let range = region.height_range();
+    let (min_y, _) = range;
     ImageBuffer::from_fn(16 * 32, 16 * 32, |x_block, z_block| {
         let (x_block, z_block) = (x_block as usize, z_block as usize);
-        for y in (0..256).rev() {
+        // The highest block was already found when computing the heights
+        let start_y = match heights {
+            Some(heights) => match heights.get(x_block as i32, z_block as i32) {
+                Some(height) => height,
+                None => return image::Rgb([0, 0, 0]),
+            },
+            None => start_height(region, range, ignore, x_block, z_block, roof),
+        };
+        for y in (min_y..=start_y).rev() {
             let candidate = region.get_block(x_block, y, z_block);
             if !ignore.contains(candidate) {
                 let properties = region.get_gprop(x_block, y, z_block);
@@ -49,7 +196,15 @@ pub fn image_chunk(
                 if let Some(index) = index {
                     let textures = textures.read().unwrap();
                     let (_, _, avg) = textures.get_texture(index);
-                    return image::Rgb(*avg);
+                    let biome = region.get_biome(x_block, y, z_block);
+                    let mut color = match textures.biome_color(candidate, biome) {
+                        Some(tint) => biome::tint_color(*avg, tint),
+                        None => *avg,
+                    };
+                    if let Some(heights) = heights {
+                        tint_height(&mut color, heights.shade(x_block, z_block, y));
+                    }
+                    return image::Rgb(color);
                 }
             }
         }
@@ -57,15 +212,37 @@ pub fn image_chunk(
     })
 }
 
+// Colors each column by the biome of its highest block
+pub fn image_biomes(
+    region: &Region,
+    ignore: &HashSet<String>,
+    colors: &[[u8; 3]],
+    roof: Option<i32>,
+) -> image::RgbImage {
+    let range = region.height_range();
+    ImageBuffer::from_fn(16 * 32, 16 * 32, |x_block, z_block| {
+        let (x_block, z_block) = (x_block as usize, z_block as usize);
+        let height = column_height(region, range, ignore, x_block, z_block, roof);
+        height.map_or(image::Rgb([0, 0, 0]), |y| {
+            let biome = region.get_biome(x_block, y, z_block);
+            image::Rgb(colors[usize::from(biome)])
+        })
+    })
+}
+
 pub fn image_chunk_textures(
     region: &Region,
     ignore: &HashSet<String>,
     textures: &RwLock<TextureLoader>,
+    roof: Option<i32>,
 ) -> image::RgbaImage {
     let mut img = ImageBuffer::new(16 * 32 * 16, 16 * 32 * 16);
+    let range = region.height_range();
+    let (min_y, _) = range;
     for x in 0..(16 * 32) {
         for z in 0..(16 * 32) {
-            for y in (0..256).rev() {
+            let start_y = start_height(region, range, ignore, x, z, roof);
+            for y in (min_y..=start_y).rev() {
                 let candidate = region.get_block(x, y, z);
                 if !ignore.contains(candidate) {
                     let properties = region.get_gprop(x, y, z);
@@ -82,7 +259,8 @@ pub fn image_chunk_textures(
                     if let Some(index) = index {
                         let textures = textures.read().unwrap();
                         let (texture, is_trasparent, _) = textures.get_texture(index);
-                        overlay(&mut img, &texture, x as u32 * 16, z as u32 * 16);
+                        let tint = textures.biome_color(candidate, region.get_biome(x, y, z));
+                        overlay(&mut img, texture, x as u32 * 16, z as u32 * 16, tint);
 
                         // If this block is trasparent find the lower blocks
                         if !is_trasparent {
@@ -96,3 +274,61 @@ pub fn image_chunk_textures(
 
     img
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The heights of a region and its neighbours, given by height(x, z) with x and z from -1
+    fn heights<F: Fn(i32, i32) -> Option<i32>>(height: F) -> Heights {
+        let side = 0..REGION_BLOCKS as i32;
+        Heights {
+            columns: (0..REGION_BLOCKS * REGION_BLOCKS)
+                .map(|i| height((i % REGION_BLOCKS) as i32, (i / REGION_BLOCKS) as i32))
+                .collect(),
+            north: side.clone().map(|x| height(x, -1)).collect(),
+            west: side.map(|z| height(-1, z)).collect(),
+        }
+    }
+
+    #[test]
+    fn flat_plain() {
+        let plain = heights(|_, _| Some(SEA_LEVEL));
+        for (x, z) in [(0, 0), (5, 0), (0, 9), (100, 300), (511, 511)] {
+            assert_eq!(plain.shade(x, z, SEA_LEVEL), 224);
+        }
+        // Higher plains are a little lighter
+        let plain = heights(|_, _| Some(SEA_LEVEL + 40));
+        assert_eq!(plain.shade(10, 10, SEA_LEVEL + 40), 234);
+    }
+
+    #[test]
+    fn north_facing_slope() {
+        // The ground goes down towards the north, so it faces the light
+        let slope = heights(|_, z| Some(SEA_LEVEL + z));
+        assert_eq!(slope.shade(10, 1, SEA_LEVEL + 1), 224 + 12);
+        // Across the border with the region to the north too
+        assert_eq!(slope.shade(10, 0, SEA_LEVEL), 224 + 12);
+        // Steep slopes stop getting lighter
+        let cliff = heights(|_, z| Some(SEA_LEVEL + z * 20));
+        assert_eq!(cliff.shade(10, 1, SEA_LEVEL + 20), 224 + 64 + 5);
+        // The other way it faces away from the light
+        let slope = heights(|_, z| Some(SEA_LEVEL - z));
+        assert_eq!(slope.shade(10, 1, SEA_LEVEL - 1), 224 - 12);
+    }
+
+    #[test]
+    fn missing_neighbours() {
+        // Without the regions around it, the border is shaded as if it was flat
+        let slope = heights(|x, z| match (x, z) {
+            (-1, _) | (_, -1) => None,
+            _ => Some(SEA_LEVEL + x + z),
+        });
+        assert_eq!(slope.shade(0, 0, SEA_LEVEL), 224);
+        assert_eq!(slope.shade(0, 5, SEA_LEVEL + 5), 224 + 12 + 1);
+        assert_eq!(slope.shade(5, 5, SEA_LEVEL + 10), 224 + 24 + 2);
+        // Columns without blocks are skipped too
+        let holes = heights(|x, _| if x % 2 == 0 { None } else { Some(SEA_LEVEL) });
+        assert_eq!(holes.shade(1, 1, SEA_LEVEL + 1), 224 + 12);
+    }
+}